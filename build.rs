@@ -1,36 +1,33 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
-//! For many projects this is optional, as the linker always searches the
-//! project root directory -- wherever `Cargo.toml` is. However, if you
-//! are using a workspace or have a more complicated build setup, this
-//! build script becomes required. Additionally, by requesting that
-//! Cargo re-run the build script whenever `memory.x` is changed,
-//! updating `memory.x` ensures a rebuild of the application with the
-//! new memory settings.
+//! This build script writes the `memory.x` of the selected board into a directory where the
+//! linker can always find it at build time, and sets the linker flags to tell it which link script
+//! to use.
 //!
-//! The build script also sets the linker flags to tell it which link script to use.
+//! It also checks `keyboard.toml` for inconsistencies RMK would only catch at run time, if at all
+//! (like a pin wired to two things on the same half), and turns the firmware specific sections of
+//! `keyboard.toml` into constants, and the layout into the Vial definition when Vial is enabled.
 
 use std::env;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[path = "build/board.rs"]
+mod board;
+#[path = "build/memory.rs"]
+mod memory;
+#[path = "build/validate.rs"]
+mod validate;
+#[path = "build/vial.rs"]
+mod vial;
 
 fn main() {
-    // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // `memory.x` is generated in our output directory, put it on the linker search path.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
-    File::create(out.join("memory.x"))
-        .unwrap()
-        .write_all(include_bytes!("memory.x"))
-        .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
-    // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
-    // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
-
     // Specify linker arguments.
 
     // `--nmagic` is required if memory section addresses are not aligned to 0x10000,
@@ -46,4 +43,590 @@ fn main() {
 
     // Use flip-link overflow check: https://github.com/knurling-rs/flip-link
     println!("cargo:rustc-linker=flip-link");
+
+    // Generate `config.rs` from the sections of `keyboard.toml` that RMK does not handle.
+    let keyboard_toml_path = PathBuf::from(env::var_os("KEYBOARD_TOML_PATH").unwrap());
+    println!("cargo:rerun-if-env-changed=KEYBOARD_TOML_PATH");
+    println!("cargo:rerun-if-changed={}", keyboard_toml_path.display());
+    let keyboard_toml_text = fs::read_to_string(&keyboard_toml_path).unwrap();
+    let with_display = env::var_os("CARGO_FEATURE_DISPLAY").is_some();
+    validate::validate_keyboard_toml(
+        &keyboard_toml_text,
+        &keyboard_toml_path.display().to_string(),
+        with_display,
+    );
+    let keyboard_toml: toml::Table = keyboard_toml_text.parse().unwrap();
+    // Checked by `validate_keyboard_toml`
+    let profile = board::BoardProfile::selected(&keyboard_toml).unwrap();
+    let flash_layout = memory::FlashLayout::from_keyboard_toml(&keyboard_toml, profile);
+    fs::write(out.join("memory.x"), flash_layout.memory_x()).unwrap();
+
+    let mut config = String::new();
+    generate_build_info(&keyboard_toml_text, &mut config);
+    generate_battery_config(&keyboard_toml, profile, &mut config);
+    if with_display {
+        generate_display_config(&keyboard_toml, &mut config);
+    } else {
+        generate_status_led_config(profile, &mut config);
+    }
+    generate_settings_config(&flash_layout, &mut config);
+    generate_morse_config(&keyboard_toml, &mut config);
+    generate_layout_config(&keyboard_toml, &flash_layout, &mut config);
+    generate_log_config(&keyboard_toml, &mut config);
+    fs::write(out.join("config.rs"), config).unwrap();
+
+    let vial_enabled = keyboard_toml
+        .get("host")
+        .and_then(|host| host.get("vial_enabled"))
+        .and_then(toml::Value::as_bool)
+        .unwrap_or(false);
+    if vial_enabled {
+        generate_vial_config(&keyboard_toml, out);
+    }
+}
+
+/// Write the xz compressed Vial definition and the keyboard ID, which the `rmk_central` macro
+/// includes when `host.vial_enabled` is set.
+fn generate_vial_config(keyboard_toml: &toml::Table, out: &Path) {
+    let mut keyboard_def = Vec::new();
+    xz2::read::XzEncoder::new(vial::vial_json(keyboard_toml).as_bytes(), 6)
+        .read_to_end(&mut keyboard_def)
+        .unwrap();
+    let bytes = |bytes: &[u8]| {
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{byte:#04x}")).collect();
+        bytes.join(", ")
+    };
+    fs::write(
+        out.join("config_generated.rs"),
+        format!(
+            "pub const VIAL_KEYBOARD_DEF: &[u8] = &[{}];\npub const VIAL_KEYBOARD_ID: &[u8] = &[{}];\n",
+            bytes(&keyboard_def),
+            bytes(&vial::VIAL_KEYBOARD_ID)
+        ),
+    )
+    .unwrap();
+}
+
+/// Emit what identifies the build on the boot splash: the crate version, the git commit (with a
+/// `+` when the tree has changes), the build date and a hash of `keyboard.toml`.
+///
+/// The date honours `SOURCE_DATE_EPOCH`, for reproducible builds.
+fn generate_build_info(keyboard_toml_text: &str, config: &mut String) {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let git_hash = match git(&["rev-parse", "--short=7", "HEAD"]) {
+        Some(hash) => {
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty());
+            if dirty {
+                hash + "+"
+            } else {
+                hash
+            }
+        }
+        None => "unknown".to_string(),
+    };
+
+    let epoch_secs = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .parse()
+            .expect("SOURCE_DATE_EPOCH must be a number of seconds"),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64,
+    };
+    let (year, month, day) = civil_date(epoch_secs.div_euclid(86_400));
+
+    let keyboard_toml_hash = fnv1a(keyboard_toml_text.as_bytes());
+
+    // Only for the boot splash: RMK gives the firmware no way to show it to the host
+    let version = env::var("CARGO_PKG_VERSION").unwrap();
+    writeln!(config, "pub const FIRMWARE_VERSION: &str = {version:?};").unwrap();
+    writeln!(config, "pub const GIT_HASH: &str = {git_hash:?};").unwrap();
+    writeln!(
+        config,
+        "pub const BUILD_DATE: &str = \"{year:04}-{month:02}-{day:02}\";"
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "pub const KEYBOARD_TOML_HASH: &str = \"{keyboard_toml_hash:08x}\";"
+    )
+    .unwrap();
+}
+
+/// FNV-1a, enough to tell two configurations apart.
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Gregorian date of a day counted from 1970-01-01.
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    // From Howard Hinnant's `civil_from_days`, with years starting in March
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month as u32, day as u32)
+}
+
+/// Emit the battery ADC input, the voltage divider and the discharge curve.
+///
+/// The `[battery]` section is optional, the defaults are the battery wiring of the board.
+fn generate_battery_config(
+    keyboard_toml: &toml::Table,
+    profile: &board::BoardProfile,
+    config: &mut String,
+) {
+    let empty = toml::Table::new();
+    let battery = match keyboard_toml.get("battery") {
+        Some(toml::Value::Table(battery)) => battery,
+        Some(_) => panic!("[battery] must be a table"),
+        None => &empty,
+    };
+    let integer = |key: &str, default: i64| match battery.get(key) {
+        Some(value) => value
+            .as_integer()
+            .unwrap_or_else(|| panic!("battery.{key} must be an integer")),
+        None => default,
+    };
+
+    let adc_pin = battery
+        .get("adc_pin")
+        .map(|pin| pin.as_str().expect("battery.adc_pin must be a string"))
+        .unwrap_or(profile.battery_adc_pin);
+    let adc_input = if adc_pin == "vddh" {
+        "::embassy_nrf::saadc::VddhDiv5Input.degrade_saadc()".to_string()
+    } else {
+        format!("$p.{adc_pin}.degrade_saadc()")
+    };
+    // VDDH is internally divided by 5 before reaching the SAADC.
+    let (default_measured, default_total) = if adc_pin == "vddh" {
+        (1, 5)
+    } else if adc_pin == profile.battery_adc_pin {
+        profile.battery_divider
+    } else {
+        (1, 1)
+    };
+    let divider_measured = integer("divider_measured", default_measured);
+    let divider_total = integer("divider_total", default_total);
+    assert!(
+        divider_measured > 0 && divider_total >= divider_measured,
+        "battery.divider_measured must be positive and not greater than battery.divider_total"
+    );
+    let interval = integer("interval_secs", 60);
+    assert!(
+        interval >= 1,
+        "battery.interval_secs must be at least 1 second, not {interval}"
+    );
+
+    let mut curve: Vec<(i64, i64)> = match battery.get("curve") {
+        Some(curve) => curve
+            .as_array()
+            .expect("battery.curve must be an array of [millivolts, percent] pairs")
+            .iter()
+            .map(|point| match point.as_array().map(Vec::as_slice) {
+                Some([mv, percent]) => (
+                    mv.as_integer()
+                        .expect("battery.curve millivolts must be an integer"),
+                    percent
+                        .as_integer()
+                        .expect("battery.curve percent must be an integer"),
+                ),
+                _ => panic!("battery.curve points must be [millivolts, percent] pairs"),
+            })
+            .collect(),
+        None => vec![(4200, 100), (3700, 50), (3300, 0)],
+    };
+    curve.sort_by(|a, b| b.0.cmp(&a.0));
+    assert!(curve.len() >= 2, "battery.curve needs at least two points");
+    assert!(
+        curve
+            .iter()
+            .all(|&(_, percent)| (0..=100).contains(&percent)),
+        "battery.curve percentages must be between 0 and 100"
+    );
+    assert!(
+        curve
+            .iter()
+            .all(|&(mv, _)| (1..=i64::from(u16::MAX)).contains(&mv)),
+        "battery.curve millivolts must be between 1 and {}",
+        u16::MAX
+    );
+    for points in curve.windows(2) {
+        let [(high_mv, high_percent), (low_mv, low_percent)] = points else {
+            unreachable!()
+        };
+        assert!(
+            high_mv != low_mv,
+            "battery.curve has two points at {high_mv} mV"
+        );
+        assert!(
+            high_percent >= low_percent,
+            "battery.curve percent must not rise as the voltage falls, \
+             {low_percent}% at {low_mv} mV is above {high_percent}% at {high_mv} mV"
+        );
+    }
+
+    writeln!(config, "/// SAADC input wired to the battery.").unwrap();
+    writeln!(config, "macro_rules! battery_adc_input {{").unwrap();
+    writeln!(config, "    ($p:ident) => {{ {adc_input} }};").unwrap();
+    writeln!(config, "}}").unwrap();
+    writeln!(config, "pub(crate) use battery_adc_input;").unwrap();
+    let divider_switch = match profile.battery_enable_pin {
+        Some(pin) => format!(
+            "Some(::embassy_nrf::gpio::Output::new($p.{pin}, ::embassy_nrf::gpio::Level::Low, \
+             ::embassy_nrf::gpio::OutputDrive::Standard))"
+        ),
+        None => "None".to_string(),
+    };
+    writeln!(
+        config,
+        "/// Output kept low to connect the battery divider, on the boards which can disconnect it."
+    )
+    .unwrap();
+    writeln!(config, "macro_rules! battery_divider_switch {{").unwrap();
+    writeln!(config, "    ($p:ident) => {{ {divider_switch} }};").unwrap();
+    writeln!(config, "}}").unwrap();
+    writeln!(config, "pub(crate) use battery_divider_switch;").unwrap();
+    writeln!(
+        config,
+        "pub const BATTERY_DIVIDER_MEASURED: u32 = {divider_measured};"
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "pub const BATTERY_DIVIDER_TOTAL: u32 = {divider_total};"
+    )
+    .unwrap();
+    writeln!(config, "pub const BATTERY_INTERVAL_SECS: u64 = {interval};").unwrap();
+    writeln!(
+        config,
+        "/// Discharge curve, by decreasing voltage and never rising percent."
+    )
+    .unwrap();
+    write!(config, "pub const BATTERY_CURVE: &[(u16, u8)] = &[").unwrap();
+    for (mv, percent) in curve {
+        write!(config, "({mv}, {percent}), ").unwrap();
+    }
+    writeln!(config, "];").unwrap();
+}
+
+/// Emit the wiring of the display, from `[display]`, and the SPI interrupt binding it needs.
+///
+/// The `[display]` section is optional, the defaults match a nice!view on a nice!nano v2.
+fn generate_display_config(keyboard_toml: &toml::Table, config: &mut String) {
+    let empty = toml::Table::new();
+    let display = match keyboard_toml.get("display") {
+        Some(toml::Value::Table(display)) => display,
+        Some(_) => panic!("[display] must be a table"),
+        None => &empty,
+    };
+    let string = |key: &str, default: &str| match display.get(key) {
+        Some(value) => value
+            .as_str()
+            .unwrap_or_else(|| panic!("display.{key} must be a string"))
+            .to_string(),
+        None => default.to_string(),
+    };
+
+    let driver = string("driver", "nice!view");
+    assert!(
+        driver == "nice!view",
+        "display.driver must be \"nice!view\", the only display supported"
+    );
+    let enabled = match display.get("enabled") {
+        Some(enabled) => enabled
+            .as_bool()
+            .expect("display.enabled must be a boolean"),
+        None => true,
+    };
+    let spi = string("spi", "SPI3");
+    let interrupt = match spi.as_str() {
+        "TWISPI0" => "SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0",
+        "TWISPI1" => "SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1",
+        "SPI2" => "SPIM2_SPIS2_SPI2",
+        "SPI3" => "SPIM3",
+        _ => panic!("display.spi must be one of \"TWISPI0\", \"TWISPI1\", \"SPI2\" or \"SPI3\""),
+    };
+    let [sck, mosi, cs] = board::DISPLAY_PINS.map(|(key, default)| string(key, default));
+    let rotation = match string("rotation", "normal").as_str() {
+        "normal" => "Normal",
+        "flipped" => "Flipped",
+        _ => panic!("display.rotation must be \"normal\" or \"flipped\""),
+    };
+
+    writeln!(
+        config,
+        "/// Whether the display is drawn to, it stays blank otherwise."
+    )
+    .unwrap();
+    writeln!(config, "pub const DISPLAY_ENABLED: bool = {enabled};").unwrap();
+    writeln!(
+        config,
+        "/// Rotation of the display, until changed from the settings menu."
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "pub const DISPLAY_ROTATION: crate::nice_view::Rotation = crate::nice_view::Rotation::{rotation};"
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "pub type DisplaySpi = ::embassy_nrf::peripherals::{spi};"
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "::embassy_nrf::bind_interrupts!(pub struct DisplayIrqs {{"
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "    {interrupt} => ::embassy_nrf::spim::InterruptHandler<DisplaySpi>;"
+    )
+    .unwrap();
+    writeln!(config, "}});").unwrap();
+    writeln!(
+        config,
+        "/// SPI instance, SCK, MOSI and CS pins of the display."
+    )
+    .unwrap();
+    writeln!(config, "macro_rules! display_peripherals {{").unwrap();
+    writeln!(
+        config,
+        "    ($p:ident) => {{ ($p.{spi}, $p.{sck}, $p.{mosi}, $p.{cs}) }};"
+    )
+    .unwrap();
+    writeln!(config, "}}").unwrap();
+    writeln!(config, "pub(crate) use display_peripherals;").unwrap();
+}
+
+/// Emit the pin of the LED blinking the status codes, for the builds without a display.
+fn generate_status_led_config(profile: &board::BoardProfile, config: &mut String) {
+    let led = profile.led_pin;
+    writeln!(
+        config,
+        "/// Whether the onboard LED is lit when its pin is high."
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "pub const STATUS_LED_ACTIVE_HIGH: bool = {};",
+        profile.led_active_high
+    )
+    .unwrap();
+    writeln!(config, "/// Onboard LED, blinking the status codes.").unwrap();
+    writeln!(config, "macro_rules! status_led_pin {{").unwrap();
+    writeln!(config, "    ($p:ident) => {{ $p.{led} }};").unwrap();
+    writeln!(config, "}}").unwrap();
+    writeln!(config, "pub(crate) use status_led_pin;").unwrap();
+}
+
+/// Emit the flash addresses of the firmware pages: the settings, the crash log and the ring of
+/// the flash event log, `log.flash_pages` long.
+///
+/// The pages are placed by `FlashLayout`, right below the region RMK uses for its own `[storage]`.
+fn generate_settings_config(flash_layout: &memory::FlashLayout, config: &mut String) {
+    let event_log = flash_layout.region("EVENT_LOG");
+    let settings = flash_layout.region("SETTINGS");
+    writeln!(
+        config,
+        "/// The two flash pages the firmware settings alternate between."
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "pub const SETTINGS_PAGES: [u32; 2] = [{:#x}, {:#x}];",
+        settings.start,
+        settings.start + memory::PAGE_SIZE
+    )
+    .unwrap();
+    writeln!(config, "/// Flash page holding the last crash reports.").unwrap();
+    writeln!(
+        config,
+        "pub const CRASH_LOG_ADDR: u32 = {:#x};",
+        flash_layout.region("CRASH_LOG").start
+    )
+    .unwrap();
+    writeln!(config, "/// Ring of flash pages holding the event log.").unwrap();
+    writeln!(
+        config,
+        "pub const EVENT_LOG_FLASH_ADDR: u32 = {:#x};",
+        event_log.start
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "pub const EVENT_LOG_FLASH_PAGES: u32 = {};",
+        (event_log.end - event_log.start) / memory::PAGE_SIZE
+    )
+    .unwrap();
+}
+
+/// Emit the hold timeout of `[behavior.morse]`, which the event log tells taps from holds with.
+fn generate_morse_config(keyboard_toml: &toml::Table, config: &mut String) {
+    let timeout = keyboard_toml
+        .get("behavior")
+        .and_then(|behavior| behavior.get("morse"))
+        .and_then(|morse| morse.get("hold_timeout"));
+    let millis = match timeout {
+        Some(timeout) => {
+            let timeout = timeout.as_str().unwrap_or_else(|| {
+                panic!("behavior.morse.hold_timeout must be a string like \"250ms\"")
+            });
+            let millis = match (timeout.strip_suffix("ms"), timeout.strip_suffix('s')) {
+                (Some(millis), _) => millis.trim().parse::<u64>().ok(),
+                (None, Some(secs)) => secs.trim().parse::<u64>().ok().map(|secs| secs * 1000),
+                _ => None,
+            };
+            millis
+                .filter(|&millis| millis <= u64::from(u16::MAX))
+                .unwrap_or_else(|| {
+                    panic!(
+                        "behavior.morse.hold_timeout must be a duration like \"250ms\", not \"{timeout}\""
+                    )
+                })
+        }
+        // RMK's default
+        None => 250,
+    };
+    writeln!(config, "pub const MORSE_HOLD_TIMEOUT_MS: u16 = {millis};").unwrap();
+}
+
+/// Emit the fingerprint of the default keymap and where it is kept, to reset the stored keymap
+/// when the flashed one changes.
+///
+/// The fingerprint covers the `matrix_map`, the `[[layer]]` tables and `[behavior.morse]`, as
+/// parsed and with the whitespace collapsed: comments and alignment do not change it.
+fn generate_layout_config(
+    keyboard_toml: &toml::Table,
+    flash_layout: &memory::FlashLayout,
+    config: &mut String,
+) {
+    let mut keymap = toml::Table::new();
+    let matrix_map = keyboard_toml
+        .get("layout")
+        .and_then(|layout| layout.get("matrix_map"));
+    let morse = keyboard_toml
+        .get("behavior")
+        .and_then(|behavior| behavior.get("morse"));
+    for (key, value) in [
+        ("matrix_map", matrix_map),
+        ("layer", keyboard_toml.get("layer")),
+        ("morse", morse),
+    ] {
+        if let Some(value) = value {
+            keymap.insert(key.to_string(), collapse_whitespace(value));
+        }
+    }
+    let fingerprint = fnv1a(toml::to_string(&keymap).unwrap().as_bytes());
+
+    writeln!(config, "/// Fingerprint of the keymap of `keyboard.toml`.").unwrap();
+    writeln!(
+        config,
+        "pub const LAYOUT_FINGERPRINT: u32 = {fingerprint:#010x};"
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "/// Flash page holding the fingerprint of the stored keymap."
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "pub const LAYOUT_ADDR: u32 = {:#x};",
+        flash_layout.region("LAYOUT").start
+    )
+    .unwrap();
+}
+
+/// Copy of `value` with the whitespace of its strings collapsed, like the spaces aligning `keys`.
+fn collapse_whitespace(value: &toml::Value) -> toml::Value {
+    match value {
+        toml::Value::String(string) => {
+            toml::Value::String(string.split_whitespace().collect::<Vec<_>>().join(" "))
+        }
+        toml::Value::Array(array) => {
+            toml::Value::Array(array.iter().map(collapse_whitespace).collect())
+        }
+        toml::Value::Table(table) => toml::Value::Table(
+            table
+                .iter()
+                .map(|(key, value)| (key.clone(), collapse_whitespace(value)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Emit the event log filter used at boot, from the optional `[log]` section.
+fn generate_log_config(keyboard_toml: &toml::Table, config: &mut String) {
+    // Same bits as `LogCategory`
+    const CATEGORIES: [(&str, u8); 6] = [
+        ("power", 1 << 0),
+        ("ble", 1 << 1),
+        ("split", 1 << 2),
+        ("layer", 1 << 3),
+        ("keys", 1 << 4),
+        ("sleep", 1 << 5),
+    ];
+    let log = keyboard_toml.get("log");
+
+    let categories = match log.and_then(|log| log.get("categories")) {
+        Some(categories) => categories
+            .as_array()
+            .expect("log.categories must be an array of category names")
+            .iter()
+            .map(|category| {
+                let name = category.as_str().expect("log.categories must be strings");
+                CATEGORIES
+                    .iter()
+                    .find(|(known, _)| *known == name)
+                    .unwrap_or_else(|| {
+                        panic!("unknown log category \"{name}\", expected one of {CATEGORIES:?}")
+                    })
+                    .1
+            })
+            .fold(0, |categories, bit| categories | bit),
+        // Everything but the key events
+        None => CATEGORIES
+            .iter()
+            .filter(|(name, _)| *name != "keys")
+            .fold(0, |categories, (_, bit)| categories | bit),
+    };
+    let changes_only = match log.and_then(|log| log.get("changes_only")) {
+        Some(changes_only) => changes_only
+            .as_bool()
+            .expect("log.changes_only must be a boolean"),
+        None => true,
+    };
+
+    writeln!(config, "/// `LogCategory` bits logged at boot.").unwrap();
+    writeln!(config, "pub const LOG_CATEGORIES: u8 = {categories:#010b};").unwrap();
+    writeln!(config, "pub const LOG_CHANGES_ONLY: bool = {changes_only};").unwrap();
 }
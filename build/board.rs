@@ -0,0 +1,383 @@
+//! Controller boards the firmware supports, and the wiring of each half of the keyboard, to catch
+//! a pin given two jobs.
+//!
+//! The matrix, display and battery pins come from `keyboard.toml`, the LED and the battery
+//! divider switch from the board profile. The display and battery pins are the same on both halves.
+
+use std::ops::Range;
+
+use toml_edit::{Item, Value};
+
+/// Keys of the display pins in `[display]`, with the nice!view wiring as their default.
+pub const DISPLAY_PINS: [(&str, &str); 3] = [
+    ("sck_pin", "P0_20"),
+    ("mosi_pin", "P0_17"),
+    ("cs_pin", "P0_06"),
+];
+
+/// Where the board is selected, first found first: `board.name` for the boards RMK does not know,
+/// which read `keyboard.chip = "nrf52840"` instead, else `keyboard.board`.
+pub const BOARD_KEYS: [(&str, &str); 2] = [("board", "name"), ("keyboard", "board")];
+
+/// What differs between the nRF52840 controller boards, selected by `BOARD_KEYS`.
+///
+/// All of them ship the Adafruit nRF52 bootloader, at `0xF4000`, which RMK's `adafruit_bl`
+/// feature reboots into.
+pub struct BoardProfile {
+    /// Name in `keyboard.board` or `board.name`.
+    pub name: &'static str,
+    /// Name RMK knows the board by, `None` when it only knows the chip.
+    pub rmk_board: Option<&'static str>,
+    /// Start of the firmware, after the MBR, and the SoftDevice when the bootloader needs one.
+    pub flash_origin: u32,
+    /// Start of the bootloader, the end of the flash available to the firmware.
+    pub bootloader_start: u32,
+    /// Default `battery.adc_pin`, `divider_measured` and `divider_total`.
+    pub battery_adc_pin: &'static str,
+    pub battery_divider: (i64, i64),
+    /// Pin to drive low to connect the battery divider, if it has a switch.
+    pub battery_enable_pin: Option<&'static str>,
+    /// Onboard LED, and whether it is lit when the pin is high.
+    pub led_pin: &'static str,
+    pub led_active_high: bool,
+}
+
+pub const BOARD_PROFILES: &[BoardProfile] = &[
+    BoardProfile {
+        name: "nice!nano_v2",
+        rmk_board: Some("nice!nano_v2"),
+        flash_origin: 0x1000,
+        bootloader_start: 0xF4000,
+        // VDDH is divided by 5 inside the chip
+        battery_adc_pin: "vddh",
+        battery_divider: (1, 5),
+        battery_enable_pin: None,
+        led_pin: "P0_15",
+        led_active_high: true,
+    },
+    // nice!nano v2 clones, with the same pinout and bootloader
+    BoardProfile {
+        name: "supermini",
+        rmk_board: None,
+        flash_origin: 0x1000,
+        bootloader_start: 0xF4000,
+        battery_adc_pin: "vddh",
+        battery_divider: (1, 5),
+        battery_enable_pin: None,
+        led_pin: "P0_15",
+        led_active_high: true,
+    },
+    // Seeed XIAO nRF52840, its bootloader keeps the S140 SoftDevice below the firmware
+    BoardProfile {
+        name: "xiao_nrf52840",
+        rmk_board: None,
+        flash_origin: 0x27000,
+        bootloader_start: 0xF4000,
+        // 1M and 510k divider, connected when P0_14 is low
+        battery_adc_pin: "P0_31",
+        battery_divider: (510, 1510),
+        battery_enable_pin: Some("P0_14"),
+        // Blue of the RGB LED
+        led_pin: "P0_06",
+        led_active_high: false,
+    },
+];
+
+impl BoardProfile {
+    pub fn find(name: &str) -> Option<&'static BoardProfile> {
+        BOARD_PROFILES.iter().find(|profile| profile.name == name)
+    }
+
+    /// Board selected by `keyboard.toml`, the nice!nano v2 when it sets none.
+    pub fn selected(keyboard_toml: &toml::Table) -> Result<&'static BoardProfile, String> {
+        match BOARD_KEYS
+            .iter()
+            .find_map(|(section, key)| keyboard_toml.get(*section)?.get(*key)?.as_str())
+        {
+            Some(name) => Self::find(name)
+                .ok_or_else(|| format!("unknown board {name}, expected {}", Self::names())),
+            None => Ok(&BOARD_PROFILES[0]),
+        }
+    }
+
+    /// Names of the supported boards, for error messages.
+    pub fn names() -> String {
+        let names: Vec<String> = BOARD_PROFILES
+            .iter()
+            .map(|profile| format!("\"{}\"", profile.name))
+            .collect();
+        names.join(", ")
+    }
+}
+
+/// A pin and what it is wired to.
+#[derive(Clone)]
+pub struct PinUse {
+    pub pin: String,
+    pub role: String,
+    /// Where `keyboard.toml` assigns the pin, `None` for the fixed wiring.
+    pub span: Option<Range<usize>>,
+}
+
+/// Pins of one half, the central or a peripheral.
+pub struct Half {
+    pub name: String,
+    pub pins: Vec<PinUse>,
+}
+
+impl Half {
+    /// Pins used twice, as the first and the second use.
+    pub fn conflicts(&self) -> Vec<(&PinUse, &PinUse)> {
+        let mut conflicts = Vec::new();
+        for (index, second) in self.pins.iter().enumerate() {
+            if let Some(first) = self.pins[..index]
+                .iter()
+                .find(|first| first.pin == second.pin)
+            {
+                conflicts.push((first, second));
+            }
+        }
+        conflicts
+    }
+}
+
+pub struct Board {
+    pub halves: Vec<Half>,
+}
+
+impl Board {
+    /// Describe the halves of `keyboard.toml`, or the single board of a keyboard without
+    /// `[split]`. The display pins are only taken with the `display` feature.
+    pub fn from_keyboard_toml(root: &Item, profile: &BoardProfile, with_display: bool) -> Self {
+        let mut common = Vec::new();
+        let display = root.get("display").filter(|_| with_display);
+        for (key, default) in DISPLAY_PINS.into_iter().filter(|_| with_display) {
+            let pin = display.and_then(|display| display.get(key));
+            common.push(PinUse {
+                pin: pin.and_then(Item::as_str).unwrap_or(default).to_string(),
+                role: format!("display.{key}"),
+                span: pin.and_then(Item::span),
+            });
+        }
+        common.push(PinUse {
+            pin: profile.led_pin.to_string(),
+            role: format!("the {} LED", profile.name),
+            span: None,
+        });
+        if let Some(pin) = profile.battery_enable_pin {
+            common.push(PinUse {
+                pin: pin.to_string(),
+                role: format!("the {} battery divider switch", profile.name),
+                span: None,
+            });
+        }
+        let adc_pin = root
+            .get("battery")
+            .and_then(|battery| battery.get("adc_pin"));
+        let adc_pin_name = adc_pin
+            .and_then(Item::as_str)
+            .unwrap_or(profile.battery_adc_pin);
+        if adc_pin_name != "vddh" {
+            common.push(PinUse {
+                pin: adc_pin_name.to_string(),
+                role: "battery.adc_pin".to_string(),
+                span: adc_pin.and_then(Item::span),
+            });
+        }
+
+        let mut matrices = Vec::new();
+        match root.get("split") {
+            Some(split) => {
+                if let Some(central) = split.get("central") {
+                    matrices.push(("split.central".to_string(), central.get("matrix")));
+                }
+                let peripherals = split.get("peripheral").and_then(Item::as_array_of_tables);
+                for (index, peripheral) in peripherals.into_iter().flatten().enumerate() {
+                    matrices.push((
+                        format!("split.peripheral[{index}]"),
+                        peripheral.get("matrix"),
+                    ));
+                }
+            }
+            None => matrices.push(("keyboard".to_string(), root.get("matrix"))),
+        }
+
+        let halves = matrices
+            .into_iter()
+            .map(|(name, matrix)| {
+                let mut pins = common.clone();
+                for key in ["row_pins", "col_pins", "direct_pins"] {
+                    let list = matrix
+                        .and_then(|matrix| matrix.get(key))
+                        .and_then(Item::as_array);
+                    for (index, pin) in list.into_iter().flatten().enumerate() {
+                        matrix_pins(&mut pins, &format!("matrix.{key}[{index}]"), pin);
+                    }
+                }
+                Half { name, pins }
+            })
+            .collect();
+        Board { halves }
+    }
+}
+
+/// Add the pins of a matrix entry, a pin or, for `direct_pins`, a row of pins.
+fn matrix_pins(pins: &mut Vec<PinUse>, role: &str, pin: &Value) {
+    match pin {
+        Value::Array(row) => {
+            for (index, pin) in row.iter().enumerate() {
+                matrix_pins(pins, &format!("{role}[{index}]"), pin);
+            }
+        }
+        // `_` marks a direct pin without a key
+        Value::String(name) if name.value() != "_" => pins.push(PinUse {
+            pin: name.value().clone(),
+            role: role.to_string(),
+            span: pin.span(),
+        }),
+        _ => {}
+    }
+}
+
+/// Whether `pin` names a GPIO of the nRF52840, like `P0_20`.
+pub fn is_nrf52840_pin(pin: &str) -> bool {
+    let number = |port: &str, count| {
+        pin.strip_prefix(port)
+            .filter(|number| number.len() == 2)
+            .and_then(|number| number.parse::<u8>().ok())
+            .is_some_and(|number| number < count)
+    };
+    number("P0_", 32) || number("P1_", 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use toml_edit::ImDocument;
+
+    use super::*;
+
+    fn board(text: &str, profile: &str, with_display: bool) -> Board {
+        let document = ImDocument::parse(text).unwrap();
+        let profile = BoardProfile::find(profile).unwrap();
+        Board::from_keyboard_toml(document.as_item(), profile, with_display)
+    }
+
+    fn conflicts(board: &Board) -> Vec<String> {
+        board
+            .halves
+            .iter()
+            .flat_map(|half| {
+                half.conflicts().into_iter().map(|(first, second)| {
+                    format!(
+                        "{} {}: {} {}",
+                        half.name, second.pin, first.role, second.role
+                    )
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nrf52840_pins() {
+        for pin in ["P0_00", "P0_31", "P1_00", "P1_15"] {
+            assert!(is_nrf52840_pin(pin), "{pin}");
+        }
+        for pin in ["P0_32", "P1_16", "P0_5", "P2_00", "vddh", "D3"] {
+            assert!(!is_nrf52840_pin(pin), "{pin}");
+        }
+    }
+
+    #[test]
+    fn board_profiles() {
+        assert_eq!(BoardProfile::find("supermini").unwrap().led_pin, "P0_15");
+        assert!(BoardProfile::find("pro_micro").is_none());
+        assert_eq!(
+            BoardProfile::names(),
+            "\"nice!nano_v2\", \"supermini\", \"xiao_nrf52840\""
+        );
+    }
+
+    #[test]
+    fn selected_board() {
+        let selected = |text: &str| BoardProfile::selected(&text.parse().unwrap()).map(|p| p.name);
+        assert_eq!(
+            selected("[keyboard]\nname = \"Urchin\""),
+            Ok("nice!nano_v2")
+        );
+        assert_eq!(
+            selected("[keyboard]\nboard = \"nice!nano_v2\""),
+            Ok("nice!nano_v2")
+        );
+        assert_eq!(
+            selected("[keyboard]\nchip = \"nrf52840\"\n[board]\nname = \"xiao_nrf52840\""),
+            Ok("xiao_nrf52840")
+        );
+        assert_eq!(
+            selected("[board]\nname = \"pro_micro\""),
+            Err(format!(
+                "unknown board pro_micro, expected {}",
+                BoardProfile::names()
+            ))
+        );
+    }
+
+    #[test]
+    fn matrix_pin_on_the_display() {
+        let text = r#"
+            [split.central.matrix]
+            row_pins = ["P0_20"]
+            col_pins = ["P1_15"]
+            [[split.peripheral]]
+            [split.peripheral.matrix]
+            row_pins = ["P1_13"]
+            col_pins = ["P0_24"]
+        "#;
+        assert_eq!(
+            conflicts(&board(text, "nice!nano_v2", true)),
+            ["split.central P0_20: display.sck_pin matrix.row_pins[0]"]
+        );
+        // Without the display feature, its pins are free
+        assert!(conflicts(&board(text, "nice!nano_v2", false)).is_empty());
+    }
+
+    #[test]
+    fn board_wiring_on_the_matrix() {
+        // The XIAO has its LED on P0_06 and its battery switch on P0_14
+        let text = r#"
+            [matrix]
+            row_pins = ["P0_06"]
+            direct_pins = [["P0_14", "_"], ["_", "P0_31"]]
+        "#;
+        assert_eq!(
+            conflicts(&board(text, "xiao_nrf52840", false)),
+            [
+                "keyboard P0_06: the xiao_nrf52840 LED matrix.row_pins[0]",
+                "keyboard P0_14: the xiao_nrf52840 battery divider switch matrix.direct_pins[0][0]",
+                "keyboard P0_31: battery.adc_pin matrix.direct_pins[1][1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn display_and_battery_pins_from_keyboard_toml() {
+        let text = r#"
+            [display]
+            cs_pin = "P0_02"
+            [battery]
+            adc_pin = "P0_02"
+            [matrix]
+            row_pins = ["P0_03"]
+        "#;
+        let board = board(text, "nice!nano_v2", true);
+        assert_eq!(
+            conflicts(&board),
+            ["keyboard P0_02: display.cs_pin battery.adc_pin"]
+        );
+        let pins = &board.halves[0].pins;
+        let adc_pin = pins
+            .iter()
+            .find(|pin| pin.role == "battery.adc_pin")
+            .unwrap();
+        assert_eq!(&text[adc_pin.span.clone().unwrap()], "\"P0_02\"");
+    }
+}
@@ -0,0 +1,243 @@
+//! Flash map of the firmware: the code, then the firmware data pages and RMK's storage, between
+//! the bounds of the bootloader layout.
+
+use std::fmt::Write as _;
+
+use crate::board::BoardProfile;
+
+pub const PAGE_SIZE: u32 = 0x1000;
+const NRF52840_FLASH_END: u32 = 0x10_0000;
+
+/// A range of flash, `start..end`, reserved for something else than the code.
+pub struct Region {
+    /// Name of the region in `memory.x`.
+    pub name: &'static str,
+    pub start: u32,
+    pub end: u32,
+}
+
+pub struct FlashLayout {
+    /// What puts the bounds of the firmware, for the comment of `memory.x`.
+    description: String,
+    /// First address of the firmware.
+    pub origin: u32,
+    /// End of the flash available to the firmware, the start of the bootloader if any.
+    pub end: u32,
+    /// Regions reserved for data, sorted by address.
+    pub regions: Vec<Region>,
+}
+
+impl FlashLayout {
+    /// Build the layout from `[memory]`, `[storage]` and `[log]`, panicking if regions overlap
+    /// or do not fit between the bounds of the layout.
+    ///
+    /// `memory.layout` is `"adafruit"` (the default) for the Adafruit nRF52 bootloader of the
+    /// board, `"none"` for a firmware flashed with a probe, without bootloader, or `"custom"` with
+    /// `memory.flash_origin` and `memory.flash_end`.
+    pub fn from_keyboard_toml(keyboard_toml: &toml::Table, profile: &BoardProfile) -> Self {
+        let memory = keyboard_toml.get("memory");
+        let integer = |section: Option<&toml::Value>, name: &str, key: &str| {
+            section.and_then(|section| section.get(key)).map(|value| {
+                value
+                    .as_integer()
+                    .unwrap_or_else(|| panic!("{name}.{key} must be an integer"))
+            })
+        };
+        let layout = memory
+            .and_then(|memory| memory.get("layout"))
+            .map_or("adafruit", |layout| {
+                layout.as_str().expect("memory.layout must be a string")
+            });
+        let (description, origin, end) = match layout {
+            "adafruit" => (
+                format!("{} with the Adafruit nRF52 bootloader", profile.name),
+                profile.flash_origin,
+                profile.bootloader_start,
+            ),
+            "none" => (
+                "nRF52840 without bootloader".to_string(),
+                0,
+                NRF52840_FLASH_END,
+            ),
+            "custom" => {
+                let bound = |key| {
+                    integer(memory, "memory", key)
+                        .unwrap_or_else(|| panic!("memory.{key} must be set for the custom layout"))
+                        as u32
+                };
+                (
+                    "custom layout of [memory]".to_string(),
+                    bound("flash_origin"),
+                    bound("flash_end"),
+                )
+            }
+            _ => panic!("memory.layout must be \"adafruit\", \"none\" or \"custom\""),
+        };
+        assert!(
+            origin.is_multiple_of(PAGE_SIZE) && end.is_multiple_of(PAGE_SIZE) && origin < end,
+            "the flash of the {layout} layout, {origin:#x} to {end:#x}, must be 4K pages"
+        );
+        assert!(
+            end <= NRF52840_FLASH_END,
+            "the flash of the {layout} layout ends at {end:#x}, after the 1M of the nRF52840"
+        );
+
+        let storage = keyboard_toml.get("storage");
+        let storage_start = integer(storage, "storage", "start_addr")
+            .expect("storage.start_addr must be set to reserve the firmware pages below it")
+            as u32;
+        let storage_sectors = integer(storage, "storage", "num_sectors").unwrap_or(2) as u32;
+        let event_log_pages = integer(keyboard_toml.get("log"), "log", "flash_pages").unwrap_or(4);
+        assert!(
+            event_log_pages >= 2,
+            "log.flash_pages must be at least 2, the oldest page is erased to continue the log"
+        );
+        assert!(
+            storage_start.is_multiple_of(PAGE_SIZE),
+            "storage.start_addr must be aligned to a 4K page"
+        );
+        // Down from RMK's storage, then the ring of the event log. The settings alternate between
+        // two pages, so that a full page is only erased once the other one is written.
+        let firmware_pages = [("LAYOUT", 1), ("SETTINGS", 2), ("CRASH_LOG", 1)];
+        let firmware_size: u32 = firmware_pages
+            .iter()
+            .map(|(_, pages)| pages * PAGE_SIZE)
+            .sum();
+        let event_log_size = event_log_pages as u32 * PAGE_SIZE;
+        let event_log_start = storage_start
+            .checked_sub(firmware_size + event_log_size)
+            .expect("storage.start_addr must leave room for the firmware pages below it");
+
+        let mut regions = vec![
+            Region {
+                name: "EVENT_LOG",
+                start: event_log_start,
+                end: event_log_start + event_log_size,
+            },
+            Region {
+                name: "STORAGE",
+                start: storage_start,
+                end: storage_start + storage_sectors * PAGE_SIZE,
+            },
+        ];
+        let mut next_end = storage_start;
+        for (name, pages) in firmware_pages {
+            let start = next_end - pages * PAGE_SIZE;
+            regions.push(Region {
+                name,
+                start,
+                end: next_end,
+            });
+            next_end = start;
+        }
+        regions.sort_by_key(|region| region.start);
+
+        let layout = FlashLayout {
+            description,
+            origin,
+            end,
+            regions,
+        };
+        layout.check();
+        layout
+    }
+
+    /// Panic if two regions overlap, or if a region is outside of the firmware flash.
+    fn check(&self) {
+        let mut errors = Vec::new();
+        for region in &self.regions {
+            if region.start < self.origin || region.end > self.end {
+                errors.push(format!(
+                    "{} ({:#x} to {:#x}) is outside of the firmware flash, {:#x} to {:#x}",
+                    region.name, region.start, region.end, self.origin, self.end
+                ));
+            }
+        }
+        for pair in self.regions.windows(2) {
+            if pair[0].end > pair[1].start {
+                errors.push(format!(
+                    "{} ({:#x} to {:#x}) overlaps {} ({:#x} to {:#x})",
+                    pair[0].name,
+                    pair[0].start,
+                    pair[0].end,
+                    pair[1].name,
+                    pair[1].start,
+                    pair[1].end
+                ));
+            }
+        }
+        if self.code_end() <= self.origin {
+            errors.push("no flash is left for the code".to_string());
+        }
+        assert!(
+            errors.is_empty(),
+            "the flash layout is inconsistent, check [memory], [storage] and [log]:\n{}",
+            errors.join("\n")
+        );
+    }
+
+    /// End of the code, the start of the lowest data region.
+    pub fn code_end(&self) -> u32 {
+        self.regions
+            .first()
+            .map_or(self.end, |region| region.start)
+            .min(self.end)
+    }
+
+    pub fn region(&self, name: &str) -> &Region {
+        self.regions
+            .iter()
+            .find(|region| region.name == name)
+            .unwrap()
+    }
+
+    /// `memory.x` of the layout, with the RAM kept across resets for the crash reports.
+    ///
+    /// The data regions are listed for the map of the linker, no section is placed in them.
+    pub fn memory_x(&self) -> String {
+        let mut memory_x = String::new();
+        writeln!(memory_x, "MEMORY").unwrap();
+        writeln!(memory_x, "{{").unwrap();
+        writeln!(memory_x, "  /* {} */", self.description).unwrap();
+        writeln!(
+            memory_x,
+            "  FLASH : ORIGIN = {:#010x}, LENGTH = {:#x}",
+            self.origin,
+            self.code_end() - self.origin
+        )
+        .unwrap();
+        writeln!(
+            memory_x,
+            "  /* Firmware data and RMK storage, written at run time */"
+        )
+        .unwrap();
+        for region in &self.regions {
+            writeln!(
+                memory_x,
+                "  {} : ORIGIN = {:#010x}, LENGTH = {:#x}",
+                region.name,
+                region.start,
+                region.end - region.start
+            )
+            .unwrap();
+        }
+        memory_x.push_str(
+            "  /* The last 5K are left out: the crash report, then the stack of the bootloader */
+  RAM : ORIGIN = 0x20000008, LENGTH = 0x3EBF8
+  RETAINED : ORIGIN = 0x2003EC00, LENGTH = 1K
+}
+
+SECTIONS
+{
+  /* Neither initialized by the startup code nor used by the bootloader, kept across resets */
+  .retained (NOLOAD) : ALIGN(4)
+  {
+    KEEP(*(.retained .retained.*));
+  } > RETAINED
+}
+INSERT AFTER .uninit;
+",
+        );
+        memory_x
+    }
+}
@@ -0,0 +1,571 @@
+//! Consistency checks of `keyboard.toml` against itself, before RMK and the firmware use it.
+//!
+//! Errors are reported with the line of the culprit, as `keyboard.toml:42: message`.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use toml_edit::{ImDocument, Item, TableLike};
+
+use crate::board::{is_nrf52840_pin, Board, BoardProfile, BOARD_KEYS, BOARD_PROFILES};
+
+/// Errors found in `keyboard.toml`, pointing at their line.
+struct Diagnostics<'a> {
+    text: &'a str,
+    path: &'a str,
+    /// Messages with their line, if known.
+    errors: Vec<(usize, String)>,
+}
+
+impl Diagnostics<'_> {
+    fn error(&mut self, span: Option<Range<usize>>, message: String) {
+        match span {
+            Some(span) => {
+                let line = self.text[..span.start].matches('\n').count() + 1;
+                self.errors
+                    .push((line, format!("{}:{line}: {message}", self.path)));
+            }
+            None => self.errors.push((0, format!("{}: {message}", self.path))),
+        }
+    }
+}
+
+/// Body of a string value without its quotes, with the offset of the body in the document.
+fn string_body(text: &str, span: Range<usize>) -> (usize, &str) {
+    let raw = &text[span.clone()];
+    for quote in ["\"\"\"", "'''", "\"", "'"] {
+        if raw.len() >= 2 * quote.len() && raw.starts_with(quote) && raw.ends_with(quote) {
+            return (
+                span.start + quote.len(),
+                &raw[quote.len()..raw.len() - quote.len()],
+            );
+        }
+    }
+    (span.start, raw)
+}
+
+/// Split a `keys` string on whitespace, keeping `MT(F5, LGui)` whole, with each key's offset.
+fn split_keys(keys: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    for (index, c) in keys.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(start) = start.take() {
+                    tokens.push((start, &keys[start..index]));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(index);
+    }
+    if let Some(start) = start {
+        tokens.push((start, &keys[start..]));
+    }
+    tokens
+}
+
+/// Positions `(row, col)` of the `matrix_map`, with each position's offset.
+fn matrix_map_positions(matrix_map: &str) -> Vec<(usize, Option<(i64, i64)>)> {
+    let mut positions = Vec::new();
+    let mut rest = matrix_map;
+    let mut offset = 0;
+    while let Some(open) = rest.find('(') {
+        let Some(close) = rest[open..].find(')') else {
+            positions.push((offset + open, None));
+            break;
+        };
+        let mut fields = rest[open + 1..open + close]
+            .split(',')
+            .map(|field| field.trim().parse().ok());
+        let position = match (fields.next().flatten(), fields.next().flatten()) {
+            (Some(row), Some(col)) => Some((row, col)),
+            _ => None,
+        };
+        positions.push((offset + open, position));
+        offset += open + close + 1;
+        rest = &rest[open + close + 1..];
+    }
+    positions
+}
+
+/// What the key actions may refer to.
+struct References<'a> {
+    layers: Option<i64>,
+    morses: Option<usize>,
+    profiles: Vec<&'a str>,
+}
+
+impl References<'_> {
+    /// Check the layer, morse and profile references of a key action like `MT(F5,LGui,NAV)`.
+    fn check(&self, diagnostics: &mut Diagnostics, span: Range<usize>, action: &str) {
+        let Some((name, args)) = action
+            .strip_suffix(')')
+            .and_then(|action| action.split_once('('))
+        else {
+            return;
+        };
+        let args: Vec<&str> = args.split(',').map(str::trim).collect();
+        let index = args.first().and_then(|arg| arg.parse::<i64>().ok());
+        match name.trim() {
+            "MO" | "TO" | "TG" | "DF" | "OSL" | "TT" | "LT" | "LM" => {
+                if let (Some(layer), Some(layers)) = (index, self.layers) {
+                    if layer >= layers {
+                        diagnostics.error(
+                            Some(span.clone()),
+                            format!("`{action}` uses layer {layer}, but layout.layers is {layers}"),
+                        );
+                    }
+                }
+            }
+            "TD" => {
+                if let (Some(morse), Some(morses)) = (index, self.morses) {
+                    if morse < 0 || morse as usize >= morses {
+                        diagnostics.error(
+                            Some(span.clone()),
+                            format!(
+                                "`{action}` uses morse {morse}, but behavior.morse.morses has {morses} entries"
+                            ),
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+        // Morse profile, the third argument of tap/hold actions
+        if let ("MT" | "LT" | "TH", Some(profile)) = (name.trim(), args.get(2)) {
+            if !self.profiles.contains(profile) {
+                diagnostics.error(
+                    Some(span),
+                    format!("`{action}` uses the morse profile {profile}, which is not in behavior.morse.profiles"),
+                );
+            }
+        }
+    }
+}
+
+/// A part of the split matrix, `[split.central]` or one of `[[split.peripheral]]`.
+struct SplitPart<'a> {
+    name: String,
+    table: &'a dyn TableLike,
+}
+
+impl SplitPart<'_> {
+    fn integer(&self, key: &str) -> Option<i64> {
+        self.table.get(key).and_then(Item::as_integer)
+    }
+
+    fn span(&self, key: &str) -> Option<Range<usize>> {
+        self.table.get(key).and_then(Item::span)
+    }
+
+    fn contains(&self, (row, col): (i64, i64)) -> bool {
+        let (Some(rows), Some(cols)) = (self.integer("rows"), self.integer("cols")) else {
+            return false;
+        };
+        let row_offset = self.integer("row_offset").unwrap_or(0);
+        let col_offset = self.integer("col_offset").unwrap_or(0);
+        (row_offset..row_offset + rows).contains(&row)
+            && (col_offset..col_offset + cols).contains(&col)
+    }
+}
+
+/// Check `keyboard.toml`, panicking with every inconsistency found.
+///
+/// The display pins are only checked for the builds with a display.
+pub fn validate_keyboard_toml(text: &str, path: &str, with_display: bool) {
+    let errors = keyboard_toml_errors(text, path, with_display);
+    if !errors.is_empty() {
+        panic!("{path} is inconsistent:\n{}", errors.join("\n"));
+    }
+}
+
+/// The inconsistencies of `keyboard.toml`, in the order of their lines.
+fn keyboard_toml_errors(text: &str, path: &str, with_display: bool) -> Vec<String> {
+    let document = match ImDocument::parse(text) {
+        Ok(document) => document,
+        Err(e) => panic!("{path}: {e}"),
+    };
+    let root = document.as_item();
+    let mut diagnostics = Diagnostics {
+        text,
+        path,
+        errors: Vec::new(),
+    };
+
+    let layout = &root["layout"];
+    let rows = layout.get("rows").and_then(Item::as_integer);
+    let cols = layout.get("cols").and_then(Item::as_integer);
+    let layers = layout.get("layers").and_then(Item::as_integer);
+    let layer_tables = root.get("layer").and_then(Item::as_array_of_tables);
+
+    // Layer count
+    if let (Some(layers), Some(layer_tables)) = (layers, layer_tables) {
+        if layers as usize != layer_tables.len() {
+            diagnostics.error(
+                layout["layers"].span(),
+                format!(
+                    "layout.layers is {layers}, but there are {} [[layer]] tables",
+                    layer_tables.len()
+                ),
+            );
+        }
+    }
+
+    // Split parts
+    let split = root.get("split");
+    let mut split_parts = Vec::new();
+    if let Some(central) = split
+        .and_then(|split| split.get("central"))
+        .and_then(Item::as_table_like)
+    {
+        split_parts.push(SplitPart {
+            name: "split.central".to_string(),
+            table: central,
+        });
+    }
+    let peripherals = split
+        .and_then(|split| split.get("peripheral"))
+        .and_then(Item::as_array_of_tables);
+    for (index, peripheral) in peripherals.into_iter().flatten().enumerate() {
+        split_parts.push(SplitPart {
+            name: format!("split.peripheral[{index}]"),
+            table: peripheral,
+        });
+    }
+    for part in &split_parts {
+        let name = &part.name;
+        for (size_key, offset_key, total) in
+            [("rows", "row_offset", rows), ("cols", "col_offset", cols)]
+        {
+            let (Some(size), Some(total)) = (part.integer(size_key), total) else {
+                continue;
+            };
+            let offset = part.integer(offset_key).unwrap_or(0);
+            if offset + size > total {
+                diagnostics.error(
+                    part.span(size_key),
+                    format!(
+                        "{name} spans {size_key} {offset} to {}, but layout.{size_key} is {total}",
+                        offset + size - 1
+                    ),
+                );
+            }
+        }
+        let matrix = part.table.get("matrix");
+        for (pins_key, size_key) in [("row_pins", "rows"), ("col_pins", "cols")] {
+            let (Some(pins), Some(size)) = (
+                matrix
+                    .and_then(|matrix| matrix.get(pins_key))
+                    .and_then(Item::as_array),
+                part.integer(size_key),
+            ) else {
+                continue;
+            };
+            if pins.len() as i64 != size {
+                diagnostics.error(
+                    pins.span(),
+                    format!(
+                        "{name}.matrix.{pins_key} has {} pins, but {name}.{size_key} is {size}",
+                        pins.len()
+                    ),
+                );
+            }
+        }
+    }
+
+    // Matrix map
+    let mut key_count = None;
+    if let Some(span) = layout.get("matrix_map").and_then(Item::span) {
+        let (body_start, body) = string_body(text, span);
+        let positions = matrix_map_positions(body);
+        key_count = Some(positions.len());
+        let mut seen = HashMap::new();
+        for (offset, position) in positions {
+            let span = Some(body_start + offset..body_start + offset + 1);
+            let Some((row, col)) = position else {
+                diagnostics.error(span, "matrix_map entries must be `(row, col)`".to_string());
+                continue;
+            };
+            if rows.is_some_and(|rows| !(0..rows).contains(&row))
+                || cols.is_some_and(|cols| !(0..cols).contains(&col))
+            {
+                diagnostics.error(
+                    span.clone(),
+                    format!(
+                        "matrix_map position ({row},{col}) is outside of the {}x{} layout",
+                        rows.unwrap_or_default(),
+                        cols.unwrap_or_default()
+                    ),
+                );
+            }
+            if seen.insert((row, col), ()).is_some() {
+                diagnostics.error(
+                    span.clone(),
+                    format!("matrix_map position ({row},{col}) is used twice"),
+                );
+            }
+            if !split_parts.is_empty() {
+                let owners: Vec<&str> = split_parts
+                    .iter()
+                    .filter(|part| part.contains((row, col)))
+                    .map(|part| part.name.as_str())
+                    .collect();
+                match owners.as_slice() {
+                    [_] => {}
+                    [] => diagnostics.error(
+                        span,
+                        format!("matrix_map position ({row},{col}) is in no split matrix, check their offsets"),
+                    ),
+                    owners => diagnostics.error(
+                        span,
+                        format!(
+                            "matrix_map position ({row},{col}) is in several split matrices: {}",
+                            owners.join(", ")
+                        ),
+                    ),
+                }
+            }
+        }
+    }
+
+    // Board and pins
+    let board = BOARD_KEYS.iter().find_map(|(section, key)| {
+        let item = root.get(section)?.get(key)?;
+        Some((format!("{section}.{key}"), item, item.as_str()?))
+    });
+    let known_board = board
+        .as_ref()
+        .is_none_or(|(_, _, name)| BoardProfile::find(name).is_some());
+    let profile = match &board {
+        Some((key, item, name)) => BoardProfile::find(name).unwrap_or_else(|| {
+            diagnostics.error(
+                item.span(),
+                format!(
+                    "{key} `{name}` is not supported, use one of {}",
+                    BoardProfile::names()
+                ),
+            );
+            &BOARD_PROFILES[0]
+        }),
+        None => &BOARD_PROFILES[0],
+    };
+    // RMK reads `[keyboard]` too, and rejects the boards it does not know
+    let keyboard = root.get("keyboard");
+    let rmk_board = keyboard.and_then(|keyboard| keyboard.get("board"));
+    match (profile.rmk_board, rmk_board.and_then(Item::as_str)) {
+        // Already reported
+        _ if !known_board => {}
+        (None, Some(name)) => diagnostics.error(
+            rmk_board.and_then(Item::span),
+            format!(
+                "RMK does not know the board `{name}`, set `board.name = \"{}\"` and `keyboard.chip = \"nrf52840\"` instead",
+                profile.name
+            ),
+        ),
+        (Some(expected), Some(name)) if name != expected => diagnostics.error(
+            rmk_board.and_then(Item::span),
+            format!("keyboard.board is `{name}`, but board.name is `{expected}`"),
+        ),
+        (None, None)
+            if keyboard
+                .and_then(|keyboard| keyboard.get("chip"))
+                .and_then(Item::as_str)
+                != Some("nrf52840") =>
+        {
+            diagnostics.error(
+                board.as_ref().and_then(|(_, item, _)| item.span()),
+                format!("RMK does not know the board `{}`, set `keyboard.chip = \"nrf52840\"`", profile.name),
+            )
+        }
+        _ => {}
+    }
+    for half in Board::from_keyboard_toml(root, profile, with_display).halves {
+        for pin in &half.pins {
+            if pin.span.is_some() && !is_nrf52840_pin(&pin.pin) {
+                diagnostics.error(
+                    pin.span.clone(),
+                    format!("{} `{}` is not an nRF52840 pin", pin.role, pin.pin),
+                );
+            }
+        }
+        for (first, second) in half.conflicts() {
+            diagnostics.error(
+                second.span.clone().or(first.span.clone()),
+                format!(
+                    "{} is used twice on {}: by {} and by {}",
+                    second.pin, half.name, first.role, second.role
+                ),
+            );
+        }
+    }
+
+    // Key actions
+    let morse = root
+        .get("behavior")
+        .and_then(|behavior| behavior.get("morse"));
+    let morses = morse
+        .and_then(|morse| morse.get("morses"))
+        .and_then(Item::as_array);
+    let references = References {
+        layers,
+        morses: morses.map(|morses| morses.len()),
+        profiles: morse
+            .and_then(|morse| morse.get("profiles"))
+            .and_then(Item::as_table_like)
+            .map(|profiles| profiles.iter().map(|(name, _)| name).collect())
+            .unwrap_or_default(),
+    };
+    for layer in layer_tables.into_iter().flatten() {
+        let Some(keys) = layer.get("keys") else {
+            continue;
+        };
+        let Some(span) = keys.span() else {
+            continue;
+        };
+        let (body_start, body) = string_body(text, span.clone());
+        let tokens = split_keys(body);
+        let name = layer
+            .get("name")
+            .and_then(Item::as_str)
+            .unwrap_or("unnamed");
+        if let Some(key_count) = key_count {
+            if tokens.len() != key_count {
+                diagnostics.error(
+                    Some(span),
+                    format!(
+                        "layer `{name}` has {} keys, but layout.matrix_map has {key_count} positions",
+                        tokens.len()
+                    ),
+                );
+            }
+        }
+        for (offset, token) in tokens {
+            let start = body_start + offset;
+            references.check(&mut diagnostics, start..start + token.len(), token);
+        }
+    }
+    for morse in morses.into_iter().flatten() {
+        let Some(morse) = morse.as_inline_table() else {
+            continue;
+        };
+        for (_, action) in morse.iter() {
+            if let (Some(span), Some(action_text)) = (action.span(), action.as_str()) {
+                references.check(&mut diagnostics, span, action_text);
+            }
+        }
+    }
+
+    diagnostics.errors.sort_by_key(|&(line, _)| line);
+    diagnostics.errors.into_iter().map(|(_, e)| e).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYBOARD_TOML: &str = include_str!("../keyboard.toml");
+
+    /// The errors of `keyboard.toml` with `from` replaced by `to`, once.
+    fn errors_with(from: &str, to: &str) -> Vec<String> {
+        assert_eq!(KEYBOARD_TOML.matches(from).count(), 1, "{from}");
+        let text = KEYBOARD_TOML.replacen(from, to, 1);
+        keyboard_toml_errors(&text, "keyboard.toml", true)
+    }
+
+    /// Line of the first occurrence of `text` in `keyboard.toml`.
+    fn line_of(text: &str) -> usize {
+        let offset = KEYBOARD_TOML.find(text).unwrap();
+        KEYBOARD_TOML[..offset].matches('\n').count() + 1
+    }
+
+    #[test]
+    fn keyboard_toml_is_consistent() {
+        assert_eq!(
+            keyboard_toml_errors(KEYBOARD_TOML, "keyboard.toml", true),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            keyboard_toml_errors(KEYBOARD_TOML, "keyboard.toml", false),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn tap_dance_out_of_the_morses() {
+        assert_eq!(
+            errors_with("TD(3)", "TD(9)"),
+            [format!(
+                "keyboard.toml:{}: `TD(9)` uses morse 9, but behavior.morse.morses has 9 entries",
+                line_of("TD(3)")
+            )]
+        );
+    }
+
+    #[test]
+    fn layer_count_mismatch() {
+        assert_eq!(
+            errors_with("layers = 4", "layers = 5"),
+            [format!(
+                "keyboard.toml:{}: layout.layers is 5, but there are 4 [[layer]] tables",
+                line_of("layers = 4")
+            )]
+        );
+    }
+
+    #[test]
+    fn matrix_map_outside_of_the_split_offsets() {
+        // The peripheral moves right, the map keeps its columns 5 and on
+        let errors = errors_with("col_offset = 5", "col_offset = 6");
+        let matrix_map = line_of("(0,5,R)");
+        assert_eq!(
+            errors[0],
+            format!(
+                "keyboard.toml:{matrix_map}: matrix_map position (0,5) is in no split matrix, check their offsets"
+            )
+        );
+        let peripheral_cols = line_of("rows = 4\ncols = 5\nrow_offset = 0\ncol_offset = 5") + 1;
+        assert_eq!(
+            errors[4],
+            format!(
+                "keyboard.toml:{peripheral_cols}: split.peripheral[0] spans cols 6 to 10, but layout.cols is 10"
+            )
+        );
+        // Column 5 of the 4 rows, then the peripheral
+        assert_eq!(errors.len(), 5);
+    }
+
+    #[test]
+    fn pin_used_by_the_matrix_and_the_display() {
+        assert_eq!(
+            errors_with("cs_pin = \"P0_06\"", "cs_pin = \"P1_15\""),
+            [format!(
+                "keyboard.toml:{}: P1_15 is used twice on split.central: by display.cs_pin and by matrix.col_pins[0]",
+                line_of("col_pins = [\"P1_15\"")
+            )]
+        );
+    }
+
+    #[test]
+    fn pin_used_by_the_matrix_and_the_led() {
+        // The LED of the nice!nano is on P0_15
+        assert_eq!(
+            errors_with("\"P0_24\"", "\"P0_15\""),
+            [format!(
+                "keyboard.toml:{}: P0_15 is used twice on split.peripheral[0]: by the nice!nano_v2 LED and by matrix.col_pins[0]",
+                line_of("col_pins = [\"P0_24\"")
+            )]
+        );
+    }
+
+    #[test]
+    fn unknown_board() {
+        let errors = errors_with("board = \"nice!nano_v2\"", "board = \"pro_micro\"");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("keyboard.board `pro_micro` is not supported"));
+    }
+}
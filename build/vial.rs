@@ -0,0 +1,85 @@
+//! Vial definition of the keyboard, the layout the Vial app draws, built from `matrix_map`.
+//!
+//! The keys are placed by their matrix position, with a one key gap before the columns of each
+//! split peripheral, like the `keymap` host tool does.
+
+use json::JsonValue;
+
+/// Identifies the keyboard to the Vial app, which keeps its settings per ID.
+pub const VIAL_KEYBOARD_ID: [u8; 8] = [0x74, 0xC0, 0x00, 0x21, 0x4F, 0xC3, 0xD5, 0x3C];
+
+/// The Vial JSON of the keyboard, checked by `validate_keyboard_toml` beforehand.
+pub fn vial_json(keyboard_toml: &toml::Table) -> String {
+    let integer = |value: Option<&toml::Value>| value.and_then(toml::Value::as_integer);
+    let keyboard = keyboard_toml.get("keyboard");
+    let layout = keyboard_toml.get("layout");
+    let rows = integer(layout.and_then(|layout| layout.get("rows"))).unwrap();
+    let cols = integer(layout.and_then(|layout| layout.get("cols"))).unwrap();
+    let matrix_map = layout
+        .and_then(|layout| layout.get("matrix_map"))
+        .and_then(toml::Value::as_str)
+        .unwrap();
+    let half_starts: Vec<i64> = keyboard_toml
+        .get("split")
+        .and_then(|split| split.get("peripheral"))
+        .and_then(toml::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|peripheral| integer(peripheral.get("col_offset")))
+        .filter(|&col_offset| col_offset > 0)
+        .collect();
+
+    let mut positions: Vec<(i64, i64)> = matrix_map
+        .split('(')
+        .skip(1)
+        .map(|entry| {
+            let mut fields = entry
+                .split(')')
+                .next()
+                .unwrap()
+                .split(',')
+                .map(|field| field.trim().parse().unwrap());
+            (fields.next().unwrap(), fields.next().unwrap())
+        })
+        .collect();
+    positions.sort();
+
+    // Keyboard Layout Editor rows: a key is its "row,col" label, `{"x": n}` skips n key widths
+    let mut keymap = Vec::new();
+    for row in 0..rows {
+        let mut kle_row = Vec::new();
+        let mut x = 0;
+        for &(_, col) in positions.iter().filter(|(key_row, _)| *key_row == row) {
+            let key_x = col + half_starts.iter().filter(|&&start| start <= col).count() as i64;
+            if key_x > x {
+                let mut skip = JsonValue::new_object();
+                skip["x"] = (key_x - x).into();
+                kle_row.push(skip);
+            }
+            kle_row.push(format!("{row},{col}").into());
+            x = key_x + 1;
+        }
+        if !kle_row.is_empty() {
+            keymap.push(JsonValue::Array(kle_row));
+        }
+    }
+
+    let hex = |key: &str| {
+        let id = integer(keyboard.and_then(|keyboard| keyboard.get(key)))
+            .unwrap_or_else(|| panic!("keyboard.{key} must be set for Vial"));
+        format!("{id:#06X}").replace("0X", "0x")
+    };
+    let mut vial = JsonValue::new_object();
+    vial["name"] = keyboard
+        .and_then(|keyboard| keyboard.get("product_name"))
+        .and_then(toml::Value::as_str)
+        .unwrap_or("Urchin")
+        .into();
+    vial["vendorId"] = hex("vendor_id").into();
+    vial["productId"] = hex("product_id").into();
+    vial["lighting"] = "none".into();
+    vial["matrix"]["rows"] = rows.into();
+    vial["matrix"]["cols"] = cols.into();
+    vial["layouts"]["keymap"] = JsonValue::Array(keymap);
+    vial.dump()
+}
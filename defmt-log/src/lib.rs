@@ -0,0 +1,137 @@
+//! The defmt logger, writing to RTT like `defmt-rtt`, and with the `usb` feature to the USB serial
+//! port of the central.
+//!
+//! The code of RMK's macros links `defmt_rtt`, and the firmware depends on this crate under that
+//! name. A probe reads the frames from the RTT channel `defmt`, as with `defmt-rtt`. With `usb`,
+//! each frame is also copied for `next_frame`, which the firmware writes to the serial port, see
+//! `src/usb_log.rs`.
+
+#![no_std]
+
+use core::cell::RefCell;
+
+use critical_section::{CriticalSection, Mutex, RestoreState};
+use defmt::Encoder;
+#[cfg(feature = "usb")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use rtt_target::{rtt_init, ChannelMode, UpChannel};
+
+/// Longest frame copied for USB, the longer ones only go to RTT.
+pub const FRAME_SIZE: usize = 128;
+
+/// An encoded defmt frame, ending with its 0 delimiter.
+pub type Frame = heapless::Vec<u8, FRAME_SIZE>;
+
+/// Frames waiting for the firmware, the new ones are dropped when it falls behind.
+#[cfg(feature = "usb")]
+static FRAMES: Channel<CriticalSectionRawMutex, Frame, 8> = Channel::new();
+
+struct State {
+    /// Whether a frame is being written, between `acquire` and `release`.
+    taken: bool,
+    /// Critical section held while the frame is written.
+    restore: RestoreState,
+    encoder: Encoder,
+    /// Up channel of the RTT control block, set up by the first frame.
+    rtt: Option<UpChannel>,
+    /// Copy of the frame being written, `None` once it is too long.
+    #[cfg(feature = "usb")]
+    frame: Option<Frame>,
+}
+
+static STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State {
+    taken: false,
+    restore: RestoreState::invalid(),
+    encoder: Encoder::new(),
+    rtt: None,
+    #[cfg(feature = "usb")]
+    frame: None,
+}));
+
+/// The state, only used inside the critical section of the logger.
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    let cs = unsafe { CriticalSection::new() };
+    f(&mut STATE.borrow_ref_mut(cs))
+}
+
+/// Up channel 0 of RTT, named `defmt` as the one of `defmt-rtt`, which the probe reads.
+fn rtt_channel() -> UpChannel {
+    let channels = rtt_init! {
+        up: {
+            0: {
+                size: 1024,
+                mode: ChannelMode::NoBlockSkip,
+                name: "defmt"
+            }
+        }
+    };
+    channels.up.0
+}
+
+impl State {
+    /// Encode with `encode`, writing the encoded bytes to RTT and to the copy of the frame.
+    fn encode(&mut self, encode: impl FnOnce(&mut Encoder, &mut dyn FnMut(&[u8]))) {
+        let rtt = self.rtt.get_or_insert_with(rtt_channel);
+        #[cfg(feature = "usb")]
+        let frame = &mut self.frame;
+        encode(&mut self.encoder, &mut |bytes| {
+            rtt.write(bytes);
+            #[cfg(feature = "usb")]
+            if let Some(copy) = frame.as_mut() {
+                if copy.extend_from_slice(bytes).is_err() {
+                    *frame = None;
+                }
+            }
+        });
+    }
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let restore = unsafe { critical_section::acquire() };
+        with_state(|state| {
+            if state.taken {
+                panic!("defmt logger taken reentrantly");
+            }
+            state.taken = true;
+            state.restore = restore;
+            #[cfg(feature = "usb")]
+            {
+                state.frame = Some(Frame::new());
+            }
+            state.encode(|encoder, write| encoder.start_frame(write));
+        });
+    }
+
+    // The RTT channel never blocks, and the frames for USB are only complete at `release`
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        let restore = with_state(|state| {
+            state.encode(|encoder, write| encoder.end_frame(write));
+            #[cfg(feature = "usb")]
+            if let Some(frame) = state.frame.take() {
+                // Dropped when the firmware is behind, RTT still has it
+                let _ = FRAMES.try_send(frame);
+            }
+            state.taken = false;
+            state.restore
+        });
+        unsafe { critical_section::release(restore) };
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        with_state(|state| state.encode(|encoder, write| encoder.write(bytes, write)));
+    }
+}
+
+/// Next frame written, for the USB serial port. Without the `usb` feature, never comes.
+pub async fn next_frame() -> Frame {
+    #[cfg(feature = "usb")]
+    return FRAMES.receive().await;
+    #[cfg(not(feature = "usb"))]
+    core::future::pending().await
+}
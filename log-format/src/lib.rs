@@ -0,0 +1,600 @@
+//! Text of the controller events on the event log page, apart from the firmware so that it is
+//! tested on the host: `cargo make test-log-format`.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt::{self, Write};
+
+use embassy_time::{Duration, Instant};
+use heapless::{CapacityError, String, Vec};
+use rmk::{
+    ble::BleState,
+    event::{ControllerEvent, KeyboardEvent, KeyboardEventPos},
+    types::action::{Action, KeyAction},
+};
+use serde::Deserialize;
+
+/// Columns of the log page.
+pub const LOG_COLUMNS: usize = 16; // 68 = 4 * 16 + 4
+/// Columns of the time since the previous event, followed by a space.
+pub const LOG_TIME_COLUMNS: usize = 4;
+pub const LOG_TEXT_COLUMNS: usize = LOG_COLUMNS - LOG_TIME_COLUMNS - 1;
+/// Bytes of a formatted event, wrapped on several lines if needed.
+pub const LOG_ENTRY_BYTES: usize = 3 * LOG_TEXT_COLUMNS;
+pub type LogEntry = String<LOG_ENTRY_BYTES>;
+pub type LogTime = String<LOG_TIME_COLUMNS>;
+
+/// The fields of a `KeyboardEvent`, which RMK does not make public.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct KeyEventFields {
+    pub pressed: bool,
+    pub pos: KeyboardEventPos,
+}
+
+impl KeyEventFields {
+    /// Read through the serialized event, its only public view: the same fields in the same
+    /// order, deserialized one to one.
+    pub fn of(event: &KeyboardEvent) -> Self {
+        // A bool, the position variant and at most two bytes
+        let mut buffer = [0; 8];
+        let bytes = postcard::to_slice(event, &mut buffer).expect("a key event fits 8 bytes");
+        postcard::from_bytes(bytes).expect("a key event has these fields")
+    }
+}
+
+/// End of a cut entry, in ASCII like the font of the log page.
+const ELLIPSIS: &str = "...";
+
+/// Writes an entry, cutting it with an ellipsis when it is too long.
+#[derive(Default)]
+struct EntryWriter {
+    entry: LogEntry,
+    cut: bool,
+}
+
+impl Write for EntryWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.cut || self.entry.push(c).is_err() {
+                self.cut = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EntryWriter {
+    fn finish(mut self) -> LogEntry {
+        if self.cut {
+            while self.entry.len() + ELLIPSIS.len() > LOG_ENTRY_BYTES {
+                self.entry.pop();
+            }
+            // Fits, the entry was just shortened
+            let _ = self.entry.push_str(ELLIPSIS);
+        }
+        self.entry
+    }
+}
+
+/// Split an entry into lines of `LOG_TEXT_COLUMNS` characters.
+pub fn wrap(entry: &str) -> impl Iterator<Item = &str> {
+    let mut rest = entry;
+    core::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest
+            .char_indices()
+            .nth(LOG_TEXT_COLUMNS)
+            .map_or(rest.len(), |(index, _)| index);
+        let (line, tail) = rest.split_at(end);
+        rest = tail;
+        Some(line)
+    })
+}
+
+/// Format an event for the log, `None` for the events which are not logged.
+///
+/// An entry longer than `LOG_ENTRY_BYTES` ends with an ellipsis.
+pub fn format_event(event: &ControllerEvent) -> Option<LogEntry> {
+    let mut writer = EntryWriter::default();
+    match write_event(&mut writer, event) {
+        Ok(true) => Some(writer.finish()),
+        // The writer cuts instead of failing, only a `Debug` implementation can fail
+        Ok(false) | Err(_) => None,
+    }
+}
+
+/// Write an event, returns whether it is logged.
+fn write_event(entry: &mut impl Write, event: &ControllerEvent) -> Result<bool, fmt::Error> {
+    let mut buffer = itoa::Buffer::new();
+    match *event {
+        ControllerEvent::Battery(l) => {
+            entry.write_str("bat ")?;
+            entry.write_str(buffer.format(l))?;
+            entry.write_char('%')?;
+        }
+        ControllerEvent::ChargingState(s) => {
+            entry.write_str(if s { "charge oui" } else { "charge non" })?;
+        }
+        ControllerEvent::Layer(l) => {
+            entry.write_str("layer ")?;
+            entry.write_str(match l {
+                0 => "base",
+                1 => "nav",
+                2 => "prog",
+                3 => "peri",
+                _ => buffer.format(l),
+            })?;
+        }
+        ControllerEvent::ConnectionType(t) => {
+            entry.write_str(if t == 0 { "conn USB" } else { "conn BLE" })?;
+        }
+        ControllerEvent::SplitPeripheral(id, c) => {
+            entry.write_str("peri ")?;
+            entry.write_str(buffer.format(id))?;
+            entry.write_str(if c { " oui" } else { " non" })?;
+        }
+        ControllerEvent::SplitCentral(c) => {
+            entry.write_str(if c { "central oui" } else { "central non" })?;
+        }
+        ControllerEvent::Sleep(s) => {
+            entry.write_str(if s { "dodo oui" } else { "dodo non" })?;
+        }
+        ControllerEvent::BleState(p, s) => {
+            entry.write_str("prof ")?;
+            entry.write_str(buffer.format(p))?;
+            entry.write_str(match s {
+                BleState::Advertising => " advr",
+                BleState::Connected => " conn",
+                BleState::None => " none",
+            })?;
+        }
+        ControllerEvent::BleProfile(p) => {
+            entry.write_str("prof ")?;
+            entry.write_str(buffer.format(p))?;
+        }
+        ControllerEvent::ClearPeer => {
+            entry.write_str("clear peer")?;
+        }
+        ControllerEvent::Key(key_event, action) => {
+            // "+1,3 A": key pressed at row 1, col 3, with its action, see `TapHoldResolver`
+            let fields = KeyEventFields::of(&key_event);
+            let KeyboardEventPos::Key(pos) = fields.pos else {
+                return Ok(false);
+            };
+            entry.write_char(if fields.pressed { '+' } else { '-' })?;
+            entry.write_str(buffer.format(pos.row))?;
+            entry.write_char(',')?;
+            entry.write_str(buffer.format(pos.col))?;
+            entry.write_char(' ')?;
+            write_key_action(entry, action)?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Describe a key action: "A", "MO3" for a layer, "A/LShift" for a tap/hold key which was not
+/// resolved.
+fn write_key_action(entry: &mut impl Write, action: KeyAction) -> fmt::Result {
+    match action {
+        KeyAction::No => entry.write_str("no"),
+        KeyAction::Transparent => entry.write_str("__"),
+        KeyAction::Single(action) | KeyAction::Tap(action) => write_action(entry, action),
+        KeyAction::TapHold(tap, hold, ..) => {
+            write_action(entry, tap)?;
+            entry.write_char('/')?;
+            write_action(entry, hold)
+        }
+        action => write!(entry, "{:?}", action),
+    }
+}
+
+fn write_action(entry: &mut impl Write, action: Action) -> fmt::Result {
+    match action {
+        Action::Key(key) => write!(entry, "{:?}", key),
+        Action::Modifier(modifier) => write!(entry, "{:?}", modifier),
+        Action::LayerOn(layer) => write!(entry, "MO{}", layer),
+        Action::LayerToggle(layer) => write!(entry, "TG{}", layer),
+        Action::LayerToggleOnly(layer) => write!(entry, "TO{}", layer),
+        Action::DefaultLayer(layer) => write!(entry, "DF{}", layer),
+        action => write!(entry, "{:?}", action),
+    }
+}
+
+/// A tap/hold key pressed, until it is released.
+struct TapHoldKey {
+    event: KeyboardEvent,
+    pos: KeyboardEventPos,
+    tap: Action,
+    hold: Action,
+    pressed_at: Instant,
+    hold_timeout: Duration,
+    /// Whether the key is already logged as held.
+    held: bool,
+}
+
+/// Replaces the action of the tap/hold keys in their key events by an inferred one.
+///
+/// RMK only sends the keymap action of a key, and no event tells how it resolved it: the action
+/// is guessed. A key holding a modifier or a layer is held once RMK reports the modifier or layer
+/// change, and is logged then. Any other key is held if it is released after its hold timeout,
+/// and tapped if before, which misses the holds RMK decides earlier, like a permissive hold: both
+/// its events are logged at the release.
+pub struct TapHoldResolver {
+    pressed: Vec<TapHoldKey, 4>,
+    /// Hold timeout of the keys with no timeout in their morse profile.
+    hold_timeout: Duration,
+}
+
+impl TapHoldResolver {
+    pub const fn new(hold_timeout: Duration) -> Self {
+        Self {
+            pressed: Vec::new(),
+            hold_timeout,
+        }
+    }
+
+    /// The events to log for `event`, happening at `now`.
+    pub fn resolve(&mut self, event: &ControllerEvent, now: Instant) -> Vec<ControllerEvent, 4> {
+        let mut events = Vec::new();
+        match *event {
+            ControllerEvent::Key(key_event, KeyAction::TapHold(tap, hold, profile))
+                if KeyEventFields::of(&key_event).pressed =>
+            {
+                let key = TapHoldKey {
+                    event: key_event,
+                    pos: KeyEventFields::of(&key_event).pos,
+                    tap,
+                    hold,
+                    pressed_at: now,
+                    hold_timeout: profile
+                        .hold_timeout_ms()
+                        .map_or(self.hold_timeout, |ms| Duration::from_millis(ms.into())),
+                    held: false,
+                };
+                // Too many keys held at once, this one keeps the action of the keymap
+                if self.pressed.push(key).is_err() {
+                    let _ = events.push(*event);
+                }
+                return events;
+            }
+            ControllerEvent::Key(key_event, _) if !KeyEventFields::of(&key_event).pressed => {
+                let pos = KeyEventFields::of(&key_event).pos;
+                let index = self.pressed.iter().position(|key| key.pos == pos);
+                if let Some(key) = index.map(|index| self.pressed.swap_remove(index)) {
+                    let held = key.held || now - key.pressed_at >= key.hold_timeout;
+                    let action = KeyAction::Single(if held { key.hold } else { key.tap });
+                    if !key.held {
+                        let _ = events.push(ControllerEvent::Key(key.event, action));
+                    }
+                    let _ = events.push(ControllerEvent::Key(key_event, action));
+                    return events;
+                }
+            }
+            ControllerEvent::Modifier(_) | ControllerEvent::Layer(_) => {
+                for key in self.pressed.iter_mut().filter(|key| !key.held) {
+                    key.held = match (key.hold, event) {
+                        (Action::Modifier(_), ControllerEvent::Modifier(_)) => true,
+                        (Action::LayerOn(layer), ControllerEvent::Layer(active)) => {
+                            layer == *active
+                        }
+                        _ => false,
+                    };
+                    if key.held {
+                        let action = KeyAction::Single(key.hold);
+                        let _ = events.push(ControllerEvent::Key(key.event, action));
+                    }
+                }
+            }
+            _ => {}
+        }
+        // The held keys first, they caused the event
+        let _ = events.push(*event);
+        events
+    }
+}
+
+/// Format a duration in at most `LOG_TIME_COLUMNS` characters: "4.2s", "37s", "12m" or "5h".
+pub fn format_elapsed(elapsed: Duration) -> Result<LogTime, CapacityError> {
+    let mut time = LogTime::new();
+    let mut buffer = itoa::Buffer::new();
+    let millis = elapsed.as_millis();
+    let secs = elapsed.as_secs();
+    if millis < 10_000 {
+        time.push_str(buffer.format(millis / 1000))?;
+        time.push('.')?;
+        time.push_str(buffer.format(millis % 1000 / 100))?;
+        time.push('s')?;
+    } else if secs < 100 {
+        time.push_str(buffer.format(secs))?;
+        time.push('s')?;
+    } else if secs < 100 * 60 {
+        time.push_str(buffer.format(secs / 60))?;
+        time.push('m')?;
+    } else {
+        time.push_str(buffer.format((secs / 3600).min(999)))?;
+        time.push('h')?;
+    }
+    Ok(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use rmk::{
+        event::KeyPos,
+        types::{action::MorseProfile, keycode::KeyCode, modifier::ModifierCombination},
+    };
+
+    use super::*;
+
+    fn formatted(event: ControllerEvent) -> Option<std::string::String> {
+        format_event(&event).map(|entry| entry.as_str().into())
+    }
+
+    #[test]
+    fn battery() {
+        assert_eq!(formatted(ControllerEvent::Battery(87)).unwrap(), "bat 87%");
+    }
+
+    #[test]
+    fn charging_state() {
+        assert_eq!(
+            formatted(ControllerEvent::ChargingState(true)).unwrap(),
+            "charge oui"
+        );
+        assert_eq!(
+            formatted(ControllerEvent::ChargingState(false)).unwrap(),
+            "charge non"
+        );
+    }
+
+    #[test]
+    fn layer() {
+        assert_eq!(formatted(ControllerEvent::Layer(1)).unwrap(), "layer nav");
+        assert_eq!(formatted(ControllerEvent::Layer(7)).unwrap(), "layer 7");
+    }
+
+    #[test]
+    fn connection_type() {
+        assert_eq!(
+            formatted(ControllerEvent::ConnectionType(0)).unwrap(),
+            "conn USB"
+        );
+        assert_eq!(
+            formatted(ControllerEvent::ConnectionType(1)).unwrap(),
+            "conn BLE"
+        );
+    }
+
+    #[test]
+    fn split_peripheral() {
+        assert_eq!(
+            formatted(ControllerEvent::SplitPeripheral(0, true)).unwrap(),
+            "peri 0 oui"
+        );
+    }
+
+    #[test]
+    fn split_central() {
+        assert_eq!(
+            formatted(ControllerEvent::SplitCentral(false)).unwrap(),
+            "central non"
+        );
+    }
+
+    #[test]
+    fn sleep() {
+        assert_eq!(formatted(ControllerEvent::Sleep(true)).unwrap(), "dodo oui");
+    }
+
+    #[test]
+    fn ble_state() {
+        assert_eq!(
+            formatted(ControllerEvent::BleState(2, BleState::Advertising)).unwrap(),
+            "prof 2 advr"
+        );
+        assert_eq!(
+            formatted(ControllerEvent::BleState(0, BleState::Connected)).unwrap(),
+            "prof 0 conn"
+        );
+        assert_eq!(
+            formatted(ControllerEvent::BleState(1, BleState::None)).unwrap(),
+            "prof 1 none"
+        );
+    }
+
+    #[test]
+    fn ble_profile() {
+        assert_eq!(formatted(ControllerEvent::BleProfile(3)).unwrap(), "prof 3");
+    }
+
+    #[test]
+    fn clear_peer() {
+        assert_eq!(formatted(ControllerEvent::ClearPeer).unwrap(), "clear peer");
+    }
+
+    #[test]
+    fn key_events() {
+        let action = KeyAction::Single(Action::Key(KeyCode::A));
+        assert_eq!(
+            formatted(ControllerEvent::Key(KeyboardEvent::key(1, 3, true), action)).unwrap(),
+            "+1,3 A"
+        );
+        let action = KeyAction::Single(Action::LayerOn(3));
+        assert_eq!(
+            formatted(ControllerEvent::Key(
+                KeyboardEvent::key(0, 12, false),
+                action
+            ))
+            .unwrap(),
+            "-0,12 MO3"
+        );
+    }
+
+    #[test]
+    fn long_key_is_cut() {
+        let action = KeyAction::Single(Action::KeyWithModifier(
+            KeyCode::A,
+            ModifierCombination::new_from(false, true, true, true, true),
+        ));
+        let entry =
+            formatted(ControllerEvent::Key(KeyboardEvent::key(1, 3, true), action)).unwrap();
+        assert_eq!(entry.len(), LOG_ENTRY_BYTES);
+        assert!(entry.starts_with("+1,3 KeyWithModifier("));
+        assert!(entry.ends_with(ELLIPSIS));
+    }
+
+    #[test]
+    fn modifier_is_not_logged() {
+        assert_eq!(
+            formatted(ControllerEvent::Modifier(ModifierCombination::new())),
+            None
+        );
+    }
+
+    #[test]
+    fn wpm_is_not_logged() {
+        assert_eq!(formatted(ControllerEvent::Wpm(60)), None);
+    }
+
+    #[test]
+    fn key_event_fields() {
+        let fields = KeyEventFields::of(&KeyboardEvent::key(1, 3, true));
+        assert!(fields.pressed);
+        assert_eq!(fields.pos, KeyboardEventPos::Key(KeyPos { row: 1, col: 3 }));
+        let fields = KeyEventFields::of(&KeyboardEvent::key(4, 0, false));
+        assert!(!fields.pressed);
+        assert_eq!(fields.pos, KeyboardEventPos::Key(KeyPos { row: 4, col: 0 }));
+    }
+
+    fn key(row: u8, col: u8, pressed: bool, action: KeyAction) -> ControllerEvent {
+        ControllerEvent::Key(KeyboardEvent::key(row, col, pressed), action)
+    }
+
+    fn resolved(
+        resolver: &mut TapHoldResolver,
+        event: ControllerEvent,
+        millis: u64,
+    ) -> std::vec::Vec<std::string::String> {
+        resolver
+            .resolve(&event, Instant::from_millis(millis))
+            .iter()
+            .filter_map(|event| format_event(event).map(|entry| entry.as_str().into()))
+            .collect()
+    }
+
+    fn tap_hold(tap: Action, hold: Action) -> KeyAction {
+        KeyAction::TapHold(tap, hold, MorseProfile::default())
+    }
+
+    #[test]
+    fn tap_hold_key_tapped() {
+        let mut resolver = TapHoldResolver::new(Duration::from_millis(250));
+        let action = tap_hold(
+            Action::Key(KeyCode::A),
+            Action::Modifier(ModifierCombination::new_from(
+                false, false, false, true, false,
+            )),
+        );
+        assert!(resolved(&mut resolver, key(1, 3, true, action), 0).is_empty());
+        assert_eq!(
+            resolved(&mut resolver, key(1, 3, false, action), 100),
+            ["+1,3 A", "-1,3 A"]
+        );
+    }
+
+    #[test]
+    fn tap_hold_key_held_for_a_modifier() {
+        let mut resolver = TapHoldResolver::new(Duration::from_millis(250));
+        let shift = ModifierCombination::new_from(false, false, false, true, false);
+        let action = tap_hold(Action::Key(KeyCode::A), Action::Modifier(shift));
+        assert!(resolved(&mut resolver, key(1, 3, true, action), 0).is_empty());
+        let held = resolved(&mut resolver, ControllerEvent::Modifier(shift), 250);
+        assert_eq!(held.len(), 1);
+        assert!(held[0].starts_with("+1,3 "));
+        assert!(!held[0].ends_with(" A"));
+        let released = resolved(&mut resolver, key(1, 3, false, action), 400);
+        assert_eq!(released, [held[0].replacen('+', "-", 1)]);
+    }
+
+    #[test]
+    fn tap_hold_key_held_for_a_layer() {
+        let mut resolver = TapHoldResolver::new(Duration::from_millis(250));
+        let action = tap_hold(Action::Key(KeyCode::Space), Action::LayerOn(1));
+        assert!(resolved(&mut resolver, key(2, 5, true, action), 0).is_empty());
+        assert_eq!(
+            resolved(&mut resolver, ControllerEvent::Layer(1), 250),
+            ["+2,5 MO1", "layer nav"]
+        );
+        assert_eq!(
+            resolved(&mut resolver, ControllerEvent::Layer(0), 400),
+            ["layer base"]
+        );
+        assert_eq!(
+            resolved(&mut resolver, key(2, 5, false, action), 400),
+            ["-2,5 MO1"]
+        );
+    }
+
+    #[test]
+    fn tap_hold_key_held_past_its_timeout() {
+        let mut resolver = TapHoldResolver::new(Duration::from_millis(250));
+        let action = tap_hold(Action::Key(KeyCode::A), Action::Key(KeyCode::B));
+        assert!(resolved(&mut resolver, key(1, 3, true, action), 0).is_empty());
+        assert_eq!(
+            resolved(&mut resolver, key(1, 3, false, action), 300),
+            ["+1,3 B", "-1,3 B"]
+        );
+    }
+
+    #[test]
+    fn other_keys_are_not_resolved() {
+        let mut resolver = TapHoldResolver::new(Duration::from_millis(250));
+        let action = KeyAction::Single(Action::Key(KeyCode::A));
+        assert_eq!(
+            resolved(&mut resolver, key(1, 3, true, action), 0),
+            ["+1,3 A"]
+        );
+        assert_eq!(
+            resolved(&mut resolver, key(1, 3, false, action), 500),
+            ["-1,3 A"]
+        );
+    }
+
+    #[test]
+    fn entry_is_cut_with_an_ellipsis() {
+        let mut writer = EntryWriter::default();
+        writer.write_str(&"x".repeat(LOG_ENTRY_BYTES)).unwrap();
+        assert_eq!(writer.finish().as_str(), "x".repeat(LOG_ENTRY_BYTES));
+
+        let mut writer = EntryWriter::default();
+        writer.write_str(&"x".repeat(LOG_ENTRY_BYTES)).unwrap();
+        writer.write_str("y").unwrap();
+        let expected = "x".repeat(LOG_ENTRY_BYTES - ELLIPSIS.len()) + ELLIPSIS;
+        assert_eq!(writer.finish().as_str(), expected);
+    }
+
+    #[test]
+    fn wrap_lines() {
+        assert_eq!(wrap("").count(), 0);
+        assert_eq!(wrap("bat 87%").collect::<std::vec::Vec<_>>(), ["bat 87%"]);
+        let entry = "a".repeat(LOG_TEXT_COLUMNS) + "bb";
+        assert_eq!(
+            wrap(&entry).collect::<std::vec::Vec<_>>(),
+            ["a".repeat(LOG_TEXT_COLUMNS).as_str(), "bb"]
+        );
+    }
+
+    #[test]
+    fn elapsed() {
+        let format = |millis| format_elapsed(Duration::from_millis(millis)).unwrap();
+        assert_eq!(format(4_200), "4.2s");
+        assert_eq!(format(37_000), "37s");
+        assert_eq!(format(12 * 60_000), "12m");
+        assert_eq!(format(5 * 3_600_000), "5h");
+        assert_eq!(format(2_000 * 3_600_000), "999h");
+    }
+}
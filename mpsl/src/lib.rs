@@ -0,0 +1,86 @@
+//! `nrf-mpsl`, with its flash shared between RMK's storage and the firmware data pages.
+//!
+//! The firmware depends on this crate under the name `nrf_mpsl`, so the `Flash::take` in the
+//! code of RMK's macros takes the flash here, for everyone. Each handle of the flash goes through
+//! the same `nrf_mpsl::Flash`, one operation at a time: the writes and erases happen in MPSL
+//! timeslots, between the radio events, and never race on the NVMC.
+
+#![no_std]
+
+use embassy_nrf::{nvmc::FLASH_SIZE, peripherals::NVMC, Peri};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    mutex::{Mutex, MutexGuard},
+    once_lock::OnceLock,
+};
+use embedded_storage_async::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, ReadNorFlash};
+pub use nrf_mpsl::*;
+
+type MpslFlash = nrf_mpsl::Flash<'static>;
+
+type SharedFlash = Mutex<CriticalSectionRawMutex, MpslFlash>;
+
+static FLASH: OnceLock<SharedFlash> = OnceLock::new();
+
+/// A handle of the flash, the operations of all handles are serialized.
+pub struct Flash {
+    flash: &'static SharedFlash,
+}
+
+impl Flash {
+    /// Take the flash, once, from RMK's macros.
+    pub fn take(
+        mpsl: &'static MultiprotocolServiceLayer<'static>,
+        nvmc: Peri<'static, NVMC>,
+    ) -> Self {
+        if FLASH.init(Mutex::new(MpslFlash::take(mpsl, nvmc))).is_err() {
+            panic!("The flash is already taken");
+        }
+        Self::shared()
+    }
+
+    /// Another handle of the flash, once RMK's macros took it: they do before creating the
+    /// controllers.
+    pub fn shared() -> Self {
+        let Some(flash) = FLASH.try_get() else {
+            panic!("The flash is not taken yet");
+        };
+        Self { flash }
+    }
+
+    async fn lock(&self) -> MutexGuard<'static, CriticalSectionRawMutex, MpslFlash> {
+        self.flash.lock().await
+    }
+}
+
+impl ErrorType for Flash {
+    type Error = <MpslFlash as ErrorType>::Error;
+}
+
+impl ReadNorFlash for Flash {
+    const READ_SIZE: usize = <MpslFlash as ReadNorFlash>::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        ReadNorFlash::read(&mut *self.lock().await, offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl NorFlash for Flash {
+    const WRITE_SIZE: usize = <MpslFlash as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <MpslFlash as NorFlash>::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        NorFlash::erase(&mut *self.lock().await, from, to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        NorFlash::write(&mut *self.lock().await, offset, bytes).await
+    }
+}
+
+// The NVMC clears bits of a word written before, like `embassy_nrf::nvmc::Nvmc`
+impl MultiwriteNorFlash for Flash {}
@@ -0,0 +1,18 @@
+//! The panic handler, handed to the firmware's crash reports.
+//!
+//! The code of RMK's macros links `panic_probe`, and the firmware depends on this crate under that
+//! name: the panic handler is this one, which calls `urchin_panic` of the firmware, defined in
+//! `src/crash.rs` next to the other crash handlers.
+
+#![no_std]
+
+use core::panic::PanicInfo;
+
+extern "Rust" {
+    fn urchin_panic(info: &PanicInfo) -> !;
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    unsafe { urchin_panic(info) }
+}
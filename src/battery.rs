@@ -0,0 +1,75 @@
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use embassy_nrf::{gpio::Output, saadc::Saadc, wdt::WatchdogHandle};
+use embassy_time::{Duration, Instant};
+use rmk::{
+    channel::ControllerPub,
+    controller::{Controller, PollingController},
+    event::ControllerEvent,
+};
+
+use crate::{
+    battery_curve::battery_percent,
+    config::{
+        BATTERY_CURVE, BATTERY_DIVIDER_MEASURED, BATTERY_DIVIDER_TOTAL, BATTERY_INTERVAL_SECS,
+    },
+    watchdog::WATCHDOG_FEED_INTERVAL,
+};
+
+/// Last measured battery voltage, in millivolts, 0 until the first measurement.
+static BATTERY_MILLIVOLTS: AtomicU16 = AtomicU16::new(0);
+
+/// Last measured battery voltage, in millivolts.
+pub fn battery_millivolts() -> u16 {
+    BATTERY_MILLIVOLTS.load(Ordering::Relaxed)
+}
+
+/// Sample the battery voltage and publish it as a `ControllerEvent::Battery`.
+pub struct BatteryMonitor<'a> {
+    pub publisher: ControllerPub,
+    pub saadc: Saadc<'a, 1>,
+    /// Held low to connect the battery divider, on the boards which can disconnect it.
+    #[allow(dead_code)]
+    pub divider_switch: Option<Output<'a>>,
+    /// Fed at each poll, more often than the measurements: a measurement which never ends
+    /// resets the half.
+    pub watchdog: WatchdogHandle,
+    pub next_measurement: Instant,
+}
+
+impl BatteryMonitor<'_> {
+    async fn measure(&mut self) -> u16 {
+        let mut buf = [0i16; 1];
+        self.saadc.sample(&mut buf).await;
+        // 12 bit resolution, 1/6 gain and 0.6V reference: 4096 is 3600mV at the input.
+        let input_mv = buf[0].max(0) as u32 * 3600 / 4096;
+        (input_mv * BATTERY_DIVIDER_TOTAL / BATTERY_DIVIDER_MEASURED) as u16
+    }
+}
+
+impl Controller for BatteryMonitor<'_> {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, _event: Self::Event) {}
+
+    async fn next_message(&mut self) -> Self::Event {
+        core::future::pending().await
+    }
+}
+
+impl PollingController for BatteryMonitor<'_> {
+    const INTERVAL: Duration = WATCHDOG_FEED_INTERVAL;
+
+    async fn update(&mut self) {
+        self.watchdog.pet();
+        if Instant::now() < self.next_measurement {
+            return;
+        }
+        self.next_measurement = Instant::now() + Duration::from_secs(BATTERY_INTERVAL_SECS);
+        let millivolts = self.measure().await;
+        BATTERY_MILLIVOLTS.store(millivolts, Ordering::Relaxed);
+        let percent = battery_percent(BATTERY_CURVE, millivolts);
+        self.publisher
+            .publish_immediate(ControllerEvent::Battery(percent));
+    }
+}
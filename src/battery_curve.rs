@@ -0,0 +1,59 @@
+//! Charge percentage from the battery voltage, apart from the SAADC so that it is tested on the
+//! host: `cargo make test-tools`.
+
+/// Convert a battery voltage to a charge percentage, interpolated between the points of `curve`.
+///
+/// `curve` is sorted by falling voltage, with the percent never rising, as the build script
+/// checks `battery.curve`.
+pub fn battery_percent(curve: &[(u16, u8)], millivolts: u16) -> u8 {
+    let (max_mv, max_percent) = curve[0];
+    if millivolts >= max_mv {
+        return max_percent;
+    }
+    for points in curve.windows(2) {
+        let (high_mv, high_percent) = points[0];
+        let (low_mv, low_percent) = points[1];
+        if millivolts >= low_mv {
+            let span = (high_percent - low_percent) as u32;
+            let offset = (millivolts - low_mv) as u32 * span / (high_mv - low_mv) as u32;
+            return low_percent + offset as u8;
+        }
+    }
+    curve[curve.len() - 1].1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVE: &[(u16, u8)] = &[(4200, 100), (3700, 50), (3300, 0)];
+
+    #[test]
+    fn ends_of_the_curve() {
+        assert_eq!(battery_percent(CURVE, 4500), 100);
+        assert_eq!(battery_percent(CURVE, 3000), 0);
+        assert_eq!(battery_percent(CURVE, 0), 0);
+    }
+
+    #[test]
+    fn points_of_the_curve() {
+        assert_eq!(battery_percent(CURVE, 4200), 100);
+        assert_eq!(battery_percent(CURVE, 3700), 50);
+        assert_eq!(battery_percent(CURVE, 3300), 0);
+    }
+
+    #[test]
+    fn between_the_points() {
+        assert_eq!(battery_percent(CURVE, 3950), 75);
+        assert_eq!(battery_percent(CURVE, 3500), 25);
+        // Rounded down
+        assert_eq!(battery_percent(CURVE, 4199), 99);
+        assert_eq!(battery_percent(CURVE, 3301), 0);
+    }
+
+    #[test]
+    fn flat_segment() {
+        let curve = [(4200, 100), (4000, 80), (3800, 80), (3300, 0)];
+        assert_eq!(battery_percent(&curve, 3900), 80);
+    }
+}
@@ -1,305 +1,135 @@
 #![no_main]
 #![no_std]
-use defmt::unwrap;
-use embassy_nrf::{
-    gpio::{Level, Output, OutputDrive},
-    peripherals, spim,
+#[cfg(not(feature = "display"))]
+use embassy_nrf::gpio::{Output, OutputDrive};
+use embassy_nrf::saadc::{self, Input as _};
+use embassy_time::Instant;
+use rmk::{channel::CONTROLLER_CHANNEL, macros::rmk_central};
+
+use crate::{
+    battery::BatteryMonitor,
+    flash_log::FlashLog,
+    layout_version::check_layout_fingerprint,
+    log_controller::{LogController, EVENT_LOG},
+    usb_log::UsbLog,
+    watchdog::{reset_reason, start_watchdog, watchdog_handle, RmkEvents, Supervised},
 };
-use embedded_graphics::{
-    image::{Image, ImageRaw},
-    mono_font::{
-        ascii::{FONT_10X20, FONT_7X13_BOLD, FONT_9X15},
-        MonoTextStyle,
-    },
-    pixelcolor::BinaryColor,
-    prelude::Point,
-    text::Text,
-    Drawable,
+#[cfg(feature = "display")]
+use crate::{central_screen::ScreenController, display::init_display};
+#[cfg(not(feature = "display"))]
+use crate::{
+    crash::take_crash_report,
+    status_led::{BlinkCode, StatusLed, LED_OFF},
 };
-use rmk::{
-    ble::BleState,
-    channel::{ControllerSub, CONTROLLER_CHANNEL},
-    controller::Controller,
-    event::ControllerEvent,
-    macros::rmk_central,
-};
-use sharp_memory_display::*;
-
-use crate::nice_view::NiceView;
 
+// Without a display, what only the screen shows is unused
+#[cfg_attr(not(feature = "display"), allow(dead_code))]
+mod battery;
+mod battery_curve;
+#[cfg(feature = "display")]
+mod central_screen;
+mod config;
+#[cfg_attr(not(feature = "display"), allow(dead_code))]
+mod crash;
+#[cfg(feature = "display")]
+mod diagnostics;
+#[cfg(feature = "display")]
+mod display;
+mod flash;
+mod flash_log;
+mod layout_version;
+#[cfg_attr(not(feature = "display"), allow(dead_code))]
+mod log_controller;
+#[cfg(feature = "display")]
+mod menu;
+#[cfg(feature = "display")]
 mod nice_view;
+#[cfg(feature = "display")]
+mod notification;
+#[cfg(feature = "display")]
+mod pages;
+#[cfg(feature = "display")]
+mod settings;
+#[cfg(feature = "display")]
+mod settings_store;
+#[cfg(not(feature = "display"))]
+mod status_led;
+mod usb_log;
+mod watchdog;
+
+/// What shows the state of the half: the screen, or the blink codes of the LED without one.
+#[cfg(feature = "display")]
+type StatusController<'a> = Supervised<ScreenController<'a>>;
+#[cfg(not(feature = "display"))]
+type StatusController<'a> = Supervised<StatusLed<'a>>;
+
+/// Tasks with a handle of the watchdog: the status and log controllers, the battery monitor and
+/// the events of RMK.
+const SUPERVISED_TASKS: usize = 4;
 
-#[derive(Default, PartialEq)]
-enum MyBleState {
-    Advertising,
-    Connected,
-    #[default]
-    None,
-}
-
-#[rustfmt::skip]
-const BLUETOOTH_NONE_DATA: &[u8] = &[
-    0b11111111, 0b01111111, 0b11_000000,
-    0b11111111, 0b00111111, 0b11_000000,
-    0b11111111, 0b00011111, 0b11_000000,
-    0b11111111, 0b00001111, 0b11_000000,
-    0b11111111, 0b00000111, 0b11_000000,
-    0b11111111, 0b00100011, 0b11_000000,
-    0b00111111, 0b00110001, 0b11_000000,
-    0b00011111, 0b00111000, 0b11_000000,
-    0b10001111, 0b00111100, 0b01_000000,
-    0b11000111, 0b00111000, 0b11_000000,
-    0b11100011, 0b10110001, 0b11_000000,
-    0b11110001, 0b11100011, 0b11_000000,
-    0b11111000, 0b11100111, 0b11_000000,
-    0b11111100, 0b01111111, 0b11_000000,
-    0b11111110, 0b00111111, 0b11_000000,
-    0b11111110, 0b00011111, 0b11_000000,
-    0b11111100, 0b00001111, 0b11_000000,
-    0b11111000, 0b00000111, 0b11_000000,
-    0b11110001, 0b00100011, 0b11_000000,
-    0b11100011, 0b00110001, 0b11_000000,
-    0b11000111, 0b00111000, 0b11_000000,
-    0b11001111, 0b00111100, 0b01_000000,
-    0b11111111, 0b00111000, 0b00_000000,
-    0b11111111, 0b00110001, 0b11_000000,
-    0b11111111, 0b00100011, 0b11_000000,
-    0b11111111, 0b00000111, 0b11_000000,
-    0b11111111, 0b00001111, 0b11_000000,
-    0b11111111, 0b00011111, 0b11_000000,
-    0b11111111, 0b00111111, 0b11_000000,
-    0b11111111, 0b01111111, 0b11_000000,
-];
-
-#[rustfmt::skip]
-const BLUETOOTH_ADVERTISING_DATA: &[u8] = &[
-    0b11111111, 0b01111111, 0b11_000000,
-    0b11111111, 0b00111111, 0b11_000000,
-    0b11111111, 0b00011111, 0b11_000000,
-    0b11111111, 0b00001111, 0b11_000000,
-    0b11111111, 0b00000111, 0b11_000000,
-    0b11111111, 0b00100011, 0b11_000000,
-    0b11111111, 0b00110001, 0b11_000000,
-    0b11111111, 0b00111000, 0b11_000000,
-    0b11001111, 0b00111100, 0b11_000000,
-    0b11000111, 0b00111000, 0b11_000000,
-    0b11100011, 0b00110001, 0b11_000000,
-    0b11110001, 0b00100011, 0b11_000000,
-    0b11111000, 0b00000111, 0b11_000000,
-    0b11111100, 0b00001111, 0b11_000000,
-    0b11111110, 0b00011111, 0b11_000000,
-    0b11111110, 0b00011111, 0b11_000000,
-    0b11111100, 0b00001111, 0b11_000000,
-    0b11111000, 0b00000111, 0b11_000000,
-    0b11110001, 0b00100011, 0b11_000000,
-    0b11100011, 0b00110001, 0b11_000000,
-    0b11000111, 0b00111000, 0b11_000000,
-    0b11001111, 0b00111100, 0b11_000000,
-    0b11111111, 0b00111000, 0b11_000000,
-    0b11111111, 0b00110001, 0b11_000000,
-    0b11111111, 0b00100011, 0b11_000000,
-    0b11111111, 0b00000111, 0b11_000000,
-    0b11111111, 0b00001111, 0b11_000000,
-    0b11111111, 0b00011111, 0b11_000000,
-    0b11111111, 0b00111111, 0b11_000000,
-    0b11111111, 0b01111111, 0b11_000000,
-];
-
-#[rustfmt::skip]
-const BLUETOOTH_CONNECTED_DATA: &[u8] = &[
-    0b11111111, 0b01111111, 0b11_000000,
-    0b11111111, 0b00111111, 0b11_000000,
-    0b11111111, 0b00011111, 0b11_000000,
-    0b11111111, 0b00001111, 0b11_000000,
-    0b11111111, 0b00000111, 0b11_000000,
-    0b11111111, 0b00100011, 0b11_000000,
-    0b11111111, 0b00110001, 0b11_000000,
-    0b11111111, 0b00111000, 0b11_000000,
-    0b11001111, 0b00111100, 0b11_000000,
-    0b11000111, 0b00111000, 0b11_000000,
-    0b11100011, 0b00110001, 0b11_000000,
-    0b11110001, 0b00100011, 0b11_000000,
-    0b10011000, 0b00000110, 0b01_000000,
-    0b00111100, 0b00001111, 0b00_000000,
-    0b01100110, 0b00011001, 0b10_000000,
-    0b01100110, 0b00011001, 0b10_000000,
-    0b00111100, 0b00001111, 0b00_000000,
-    0b10011000, 0b00000110, 0b01_000000,
-    0b11110001, 0b00100011, 0b11_000000,
-    0b11100011, 0b00110001, 0b11_000000,
-    0b11000111, 0b00111000, 0b11_000000,
-    0b11001111, 0b00111100, 0b11_000000,
-    0b11111111, 0b00111000, 0b11_000000,
-    0b11111111, 0b00110001, 0b11_000000,
-    0b11111111, 0b00100011, 0b11_000000,
-    0b11111111, 0b00000111, 0b11_000000,
-    0b11111111, 0b00001111, 0b11_000000,
-    0b11111111, 0b00011111, 0b11_000000,
-    0b11111111, 0b00111111, 0b11_000000,
-    0b11111111, 0b01111111, 0b11_000000,
-];
-
-#[rustfmt::skip]
-const USB_DATA: &[u8] = &[
-    0b11111111, 0b11111001, 0b11111111, 0b111_00000,
-    0b11111111, 0b11000000, 0b11111111, 0b111_00000,
-    0b11111111, 0b10011001, 0b11111111, 0b111_00000,
-    0b10001111, 0b00111111, 0b11111111, 0b011_00000,
-    0b00000110, 0b01111111, 0b11111111, 0b001_00000,
-    0b00000000, 0b00000000, 0b00000000, 0b000_00000,
-    0b00000111, 0b11110011, 0b11111111, 0b001_00000,
-    0b10001111, 0b11111001, 0b11111111, 0b011_00000,
-    0b11111111, 0b11111100, 0b11111111, 0b111_00000,
-    0b11111111, 0b11111110, 0b01100011, 0b111_00000,
-    0b11111111, 0b11111111, 0b00000011, 0b111_00000,
-    0b11111111, 0b11111111, 0b11000011, 0b111_00000,
-];
-
-#[derive(Default)]
-struct ScreenState {
-    layer: u8,
-    ble_profile: u8,
-    ble_state: MyBleState,
-    connection_type: u8,
-    battery_percent: u8,
-    charging_state: bool,
-}
-
-struct ScreenController<'a> {
-    sub: ControllerSub,
-    display: NiceView<'a>,
-    current_state: ScreenState,
-}
-
-impl ScreenController<'_> {
-    fn flush_state_to_the_display(&mut self) {
-        self.display.clear_buffer();
-
-        if self.current_state.connection_type == 0 {
-            let raw_image = ImageRaw::<BinaryColor>::new(USB_DATA, 27);
-            unwrap!(Image::new(&raw_image, Point { x: 2, y: 10 }).draw(&mut self.display));
-        } else {
-            let profile = match self.current_state.ble_profile {
-                0 => "1",
-                1 => "2",
-                2 => "3",
-                _ => "?",
-            };
-            let profile_style = MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::Off);
-            let raw_image = ImageRaw::<BinaryColor>::new(
-                match self.current_state.ble_state {
-                    MyBleState::Advertising => BLUETOOTH_ADVERTISING_DATA,
-                    MyBleState::Connected => BLUETOOTH_CONNECTED_DATA,
-                    MyBleState::None => BLUETOOTH_NONE_DATA,
-                },
-                18,
-            );
-            unwrap!(Image::new(&raw_image, Point { x: 2, y: 2 }).draw(&mut self.display));
-            unwrap!(
-                Text::new(profile, Point { x: 22, y: 27 }, profile_style).draw(&mut self.display)
-            );
-        }
-        let layer = match self.current_state.layer {
-            0 => "TEXTE",
-            1 => "NAV",
-            2 => "PROG",
-            3 => "PERI",
-            _ => "",
+#[rmk_central]
+mod keyboard_central {
+    #[controller(event)]
+    fn status_controller() -> StatusController {
+        // The controllers are created before RMK opens its storage, with its config in scope
+        check_layout_fingerprint(&get_default_keymap(), &storage_config, &behavior_config).await;
+        // The first controller, the others take their handle after
+        start_watchdog::<SUPERVISED_TASKS>(p.WDT);
+        #[cfg(feature = "display")]
+        let status_controller = {
+            let (spi, sck, mosi, cs) = config::display_peripherals!(p);
+            ScreenController::new(init_display(spi, sck, mosi, cs))
         };
-        let profile_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
-        unwrap!(Text::new(layer, Point { x: 6, y: 70 }, profile_style).draw(&mut self.display));
-
-        let battery_style = MonoTextStyle::new(&FONT_9X15, BinaryColor::Off);
-
-        let mut battery_buffer = itoa::Buffer::new();
-        let battery_repr = battery_buffer.format(self.current_state.battery_percent);
-        unwrap!(
-            Text::new(battery_repr, Point { x: 32, y: 32 }, battery_style).draw(&mut self.display)
-        );
+        #[cfg(not(feature = "display"))]
+        let status_controller = StatusLed {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            led: Output::new(config::status_led_pin!(p), LED_OFF, OutputDrive::Standard),
+            pending: Some(BlinkCode::at_boot(reset_reason(), take_crash_report().as_ref())),
+            low_battery: false,
+        };
+        Supervised::new(status_controller)
+    }
 
-        self.display.flush_buffer();
+    #[controller(event)]
+    fn log_controller() -> Supervised<LogController> {
+        EVENT_LOG.lock(|log| log.borrow_mut().log_reset(reset_reason()));
+        Supervised::new(LogController {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            flash_log: FlashLog::new(reset_reason()),
+        })
     }
-}
 
-impl Controller for ScreenController<'_> {
-    type Event = ControllerEvent;
+    #[controller(poll)]
+    fn battery_monitor() -> BatteryMonitor {
+        bind_interrupts!(struct BatteryIrqs {
+            SAADC => saadc::InterruptHandler;
+        });
+        let channel_config = saadc::ChannelConfig::single_ended(config::battery_adc_input!(p));
+        let saadc = saadc::Saadc::new(
+            p.SAADC,
+            BatteryIrqs,
+            saadc::Config::default(),
+            [channel_config],
+        );
 
-    async fn process_event(&mut self, event: Self::Event) {
-        match event {
-            ControllerEvent::Layer(layer) => {
-                if layer == self.current_state.layer {
-                    return;
-                }
-                self.current_state.layer = layer;
-            }
-            ControllerEvent::Battery(battery_percent) => {
-                if battery_percent == self.current_state.battery_percent {
-                    return;
-                }
-                self.current_state.battery_percent = battery_percent;
-            }
-            ControllerEvent::BleState(profile, ble_state) => {
-                let my_ble_state = match ble_state {
-                    BleState::Advertising => MyBleState::Advertising,
-                    BleState::Connected => MyBleState::Connected,
-                    BleState::None => MyBleState::None,
-                };
-                if my_ble_state == self.current_state.ble_state
-                    && profile == self.current_state.ble_profile
-                {
-                    return;
-                }
-                self.current_state.ble_profile = profile;
-                self.current_state.ble_state = my_ble_state;
-            }
-            ControllerEvent::BleProfile(profile) => {
-                if profile == self.current_state.ble_profile {
-                    return;
-                }
-                self.current_state.ble_profile = profile;
-            }
-            ControllerEvent::ChargingState(state) => {
-                if state == self.current_state.charging_state {
-                    return;
-                }
-                self.current_state.charging_state = state;
-            }
-            ControllerEvent::ConnectionType(connection_type) => {
-                if self.current_state.connection_type == connection_type {
-                    return;
-                }
-                self.current_state.connection_type = connection_type;
-            }
-            _ => {
-                return;
-            }
+        BatteryMonitor {
+            publisher: unwrap!(CONTROLLER_CHANNEL.publisher()),
+            saadc,
+            divider_switch: config::battery_divider_switch!(p),
+            watchdog: watchdog_handle(),
+            next_measurement: Instant::now(),
         }
-        self.flush_state_to_the_display();
     }
 
-    async fn next_message(&mut self) -> Self::Event {
-        self.sub.next_message_pure().await
+    #[controller(event)]
+    fn rmk_events() -> Supervised<RmkEvents> {
+        Supervised::new(RmkEvents {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+        })
     }
-}
 
-#[rmk_central]
-mod keyboard_central {
     #[controller(event)]
-    fn screen_controller() -> ScreenController {
-        bind_interrupts!(struct Irqs {
-            SPIM3 => spim::InterruptHandler<peripherals::SPI3>;
-        });
-        let mut config = spim::Config::default();
-        config.mode = MODE;
-        let spi = spim::Spim::new_txonly(p.SPI3, Irqs, p.P0_20, p.P0_17, config);
-        let cs = Output::new(p.P0_06, Level::High, OutputDrive::Standard);
-        let mut display = NiceView::new(spi, cs);
-        display.clear();
-
-        ScreenController {
-            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
-            display,
-            current_state: ScreenState::default(),
-        }
+    fn usb_log() -> UsbLog {
+        UsbLog
     }
 }
@@ -0,0 +1,373 @@
+//! Screen of the central half, built with the `display` feature.
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{with_deadline, Duration, Instant};
+use rmk::{
+    ble::BleState,
+    channel::{ControllerSub, CONTROLLER_CHANNEL},
+    controller::Controller,
+    event::ControllerEvent,
+    types::{
+        action::{Action, KeyAction},
+        keycode::KeyCode,
+    },
+};
+use urchin_log_format::KeyEventFields;
+
+use crate::{
+    battery::battery_millivolts,
+    crash::{save_crash_report, take_crash_report, CrashReport},
+    diagnostics::{draw_boot_splash, draw_crash_report, SPLASH_DURATION},
+    log_controller::{LogCategory, EVENT_LOG, EVENT_LOG_UPDATED},
+    menu::{Menu, MenuKey, MenuOutcome},
+    nice_view::NiceView,
+    notification::{Notification, NotificationQueue},
+    pages::{draw_page, MyBleState, Page, ScreenState},
+    settings::Settings,
+    unwrap,
+    watchdog::reset_reason,
+};
+
+/// Time without changing pages after which the screen goes back to the home page.
+const PAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum ScreenEvent {
+    /// The settings read from the store, after boot.
+    SettingsLoaded(Settings),
+    Controller(ControllerEvent),
+    LogUpdated,
+    Timeout,
+}
+
+/// Screen of the central: pages, settings menu, notifications and boot screens.
+pub struct ScreenController<'a> {
+    sub: ControllerSub,
+    display: NiceView<'a>,
+    current_state: ScreenState,
+    page: Page,
+    page_changed_at: Instant,
+    notifications: NotificationQueue,
+    settings: Settings,
+    /// Settings as last saved to flash, `None` until they are read.
+    saved_settings: Option<Settings>,
+    menu: Menu,
+    last_activity: Instant,
+    asleep: bool,
+    /// End of the boot splash.
+    splash_until: Option<Instant>,
+    /// Crash which caused the last reset, shown after the splash until a key is pressed.
+    crash_report: Option<CrashReport>,
+}
+
+impl<'a> ScreenController<'a> {
+    /// Start with the boot splash, then the crash report if the last reset was a crash.
+    pub fn new(display: NiceView<'a>) -> Self {
+        let crash_report = take_crash_report();
+        let settings = Settings::DEFAULT;
+        let mut screen_controller = ScreenController {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            display,
+            current_state: ScreenState::default(),
+            page: Page::HOME,
+            page_changed_at: Instant::now(),
+            notifications: NotificationQueue::default(),
+            settings,
+            saved_settings: None,
+            menu: Menu::default(),
+            last_activity: Instant::now(),
+            asleep: false,
+            splash_until: Some(Instant::now() + SPLASH_DURATION),
+            crash_report,
+        };
+        screen_controller.apply_settings();
+        screen_controller.flush_state_to_the_display();
+        screen_controller
+    }
+
+    fn apply_settings(&mut self) {
+        self.display.set_theme(self.settings.theme);
+        self.display.set_rotation(self.settings.rotation);
+    }
+
+    /// Time at which the screen turns off for inactivity.
+    fn idle_deadline(&self) -> Option<Instant> {
+        if self.asleep {
+            return None;
+        }
+        Some(self.last_activity + self.settings.idle_timeout.duration()?)
+    }
+
+    /// Time at which the screen goes back to the home page.
+    fn page_deadline(&self) -> Option<Instant> {
+        if self.page == Page::HOME {
+            return None;
+        }
+        Some(self.page_changed_at + PAGE_TIMEOUT)
+    }
+
+    fn log_shown(&self) -> bool {
+        self.page == Page::Log
+            && !self.menu.is_open()
+            && !self.asleep
+            && self.splash_until.is_none()
+            && self.crash_report.is_none()
+    }
+
+    fn flush_state_to_the_display(&mut self) {
+        self.display.clear_buffer();
+        if self.asleep {
+            self.display.flush_buffer();
+            return;
+        }
+        if self.splash_until.is_some() {
+            draw_boot_splash(&mut self.display, "central", reset_reason());
+        } else if let Some(report) = &self.crash_report {
+            draw_crash_report(&mut self.display, report);
+        } else if self.menu.is_open() {
+            let filter = EVENT_LOG.lock(|log| log.borrow().filter());
+            self.menu.draw(&self.settings, &filter, &mut self.display);
+        } else {
+            draw_page(self.page, &mut self.current_state, &mut self.display);
+        }
+        self.notifications.draw(&mut self.display);
+        self.display.flush_buffer();
+    }
+
+    fn notify_profile(&mut self, detail: &str) {
+        let title = match self.current_state.ble_profile {
+            0 => "Profil 1",
+            1 => "Profil 2",
+            2 => "Profil 3",
+            _ => "Profil ?",
+        };
+        self.notifications.push(Notification::new(title, detail));
+    }
+
+    /// Handle a pressed key, returns whether the screen must be redrawn.
+    async fn process_key(&mut self, key: KeyCode) -> bool {
+        if key == KeyCode::User7 && !self.menu.is_open() {
+            self.page = self.page.next();
+            self.page_changed_at = Instant::now();
+            self.current_state.log_scroll = 0;
+            return true;
+        }
+        let Some(menu_key) = MenuKey::from_key_code(key) else {
+            return false;
+        };
+        if self.page == Page::Log && !self.menu.is_open() {
+            // While the menu is closed, the menu keys scroll through the log and toggle the
+            // logging of the key events
+            let scroll = &mut self.current_state.log_scroll;
+            match menu_key {
+                MenuKey::Up => *scroll = scroll.saturating_sub(1),
+                MenuKey::Down => {
+                    let max_scroll = EVENT_LOG.lock(|log| log.borrow().max_scroll());
+                    *scroll = (*scroll + 1).min(max_scroll);
+                }
+                _ => {}
+            }
+            if menu_key == MenuKey::Select {
+                EVENT_LOG.lock(|log| {
+                    let mut log = log.borrow_mut();
+                    let mut filter = log.filter();
+                    filter.toggle(LogCategory::Keys);
+                    log.set_filter(filter);
+                });
+            }
+            if menu_key != MenuKey::Toggle {
+                self.page_changed_at = Instant::now();
+                return true;
+            }
+        }
+        let mut filter = EVENT_LOG.lock(|log| log.borrow().filter());
+        let outcome = self.menu.handle(menu_key, &mut self.settings, &mut filter);
+        EVENT_LOG.lock(|log| log.borrow_mut().set_filter(filter));
+        match outcome {
+            MenuOutcome::Redraw => self.apply_settings(),
+            MenuOutcome::Closed => {
+                if self
+                    .saved_settings
+                    .is_some_and(|saved| saved != self.settings)
+                {
+                    self.settings.save().await;
+                    self.saved_settings = Some(self.settings);
+                }
+            }
+        }
+        true
+    }
+
+    /// Update the state with a controller event, returns whether the screen must be redrawn.
+    async fn update_state(&mut self, event: ControllerEvent) -> bool {
+        match event {
+            ControllerEvent::Layer(layer) => {
+                if layer == self.current_state.layer {
+                    return false;
+                }
+                self.current_state.layer = layer;
+            }
+            ControllerEvent::Battery(battery_percent) => {
+                let battery_millivolts = battery_millivolts();
+                if battery_percent == self.current_state.battery_percent
+                    && battery_millivolts == self.current_state.battery_millivolts
+                {
+                    return false;
+                }
+                self.current_state.battery_percent = battery_percent;
+                self.current_state.battery_millivolts = battery_millivolts;
+            }
+            ControllerEvent::BleState(profile, ble_state) => {
+                let my_ble_state = match ble_state {
+                    BleState::Advertising => MyBleState::Advertising,
+                    BleState::Connected => MyBleState::Connected,
+                    BleState::None => MyBleState::None,
+                };
+                if my_ble_state == self.current_state.ble_state
+                    && profile == self.current_state.ble_profile
+                {
+                    return false;
+                }
+                self.current_state.ble_profile = profile;
+                self.current_state.ble_state = my_ble_state;
+            }
+            ControllerEvent::BleProfile(profile) => {
+                if profile == self.current_state.ble_profile {
+                    return false;
+                }
+                self.current_state.ble_profile = profile;
+                self.notify_profile("actif");
+            }
+            ControllerEvent::ClearPeer => {
+                self.notify_profile("effacé");
+            }
+            ControllerEvent::SplitPeripheral(_, connected) => {
+                if connected == self.current_state.peripheral_connected {
+                    return false;
+                }
+                self.current_state.peripheral_connected = connected;
+                let detail = if !connected {
+                    "déconnectée"
+                } else if self.current_state.peripheral_was_connected {
+                    "reconnectée"
+                } else {
+                    "connectée"
+                };
+                self.current_state.peripheral_was_connected |= connected;
+                self.notifications.push(Notification::new("Droite", detail));
+            }
+            ControllerEvent::ChargingState(state) => {
+                if state == self.current_state.charging_state {
+                    return false;
+                }
+                self.current_state.charging_state = state;
+            }
+            ControllerEvent::ConnectionType(connection_type) => {
+                if self.current_state.connection_type == connection_type {
+                    return false;
+                }
+                self.current_state.connection_type = connection_type;
+                self.notifications.push(Notification::new(
+                    "Connexion",
+                    if connection_type == 0 { "USB" } else { "BLE" },
+                ));
+            }
+            ControllerEvent::Key(event, action) => {
+                let pressed = KeyEventFields::of(&event).pressed;
+                self.last_activity = Instant::now();
+                let woken_up = core::mem::replace(&mut self.asleep, false);
+                if pressed && self.splash_until.is_none() && self.crash_report.take().is_some() {
+                    return true;
+                }
+                if pressed {
+                    self.current_state
+                        .key_stats
+                        .record_press(self.last_activity);
+                }
+                let redraw = match action {
+                    KeyAction::Single(Action::Key(key)) if pressed => self.process_key(key).await,
+                    _ => false,
+                };
+                let stats_shown = self.page == Page::Stats && !self.menu.is_open();
+                if !redraw && !woken_up && !(stats_shown && pressed) {
+                    return false;
+                }
+            }
+            _ => {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Controller for ScreenController<'_> {
+    type Event = ScreenEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        let event = match event {
+            ScreenEvent::SettingsLoaded(settings) => {
+                self.settings = settings;
+                self.saved_settings = Some(settings);
+                self.apply_settings();
+                self.flush_state_to_the_display();
+                return;
+            }
+            ScreenEvent::Controller(event) => event,
+            ScreenEvent::Timeout => {
+                let now = Instant::now();
+                if self.splash_until.is_some_and(|d| d <= now) {
+                    self.splash_until = None;
+                }
+                if self.notifications.deadline().is_some_and(|d| d <= now) {
+                    self.notifications.expire();
+                }
+                if self.idle_deadline().is_some_and(|d| d <= now) {
+                    self.asleep = true;
+                }
+                if self.page_deadline().is_some_and(|d| d <= now) {
+                    self.page = Page::HOME;
+                }
+                self.flush_state_to_the_display();
+                return;
+            }
+            ScreenEvent::LogUpdated => {
+                if self.log_shown() {
+                    self.flush_state_to_the_display();
+                }
+                return;
+            }
+        };
+        if self.update_state(event).await {
+            self.flush_state_to_the_display();
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        save_crash_report().await;
+        if self.saved_settings.is_none() {
+            return ScreenEvent::SettingsLoaded(Settings::load().await);
+        }
+        let deadlines = [
+            self.splash_until,
+            self.notifications.deadline(),
+            self.idle_deadline(),
+            self.page_deadline(),
+        ];
+        let log_shown = self.log_shown();
+        let next_event = async {
+            if !log_shown {
+                return ScreenEvent::Controller(self.sub.next_message_pure().await);
+            }
+            match select(self.sub.next_message_pure(), EVENT_LOG_UPDATED.wait()).await {
+                Either::First(event) => ScreenEvent::Controller(event),
+                Either::Second(()) => ScreenEvent::LogUpdated,
+            }
+        };
+        let Some(deadline) = deadlines.into_iter().flatten().min() else {
+            return next_event.await;
+        };
+        with_deadline(deadline, next_event)
+            .await
+            .unwrap_or(ScreenEvent::Timeout)
+    }
+}
@@ -0,0 +1,3 @@
+//! Constants generated by `build.rs` from `keyboard.toml`.
+
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
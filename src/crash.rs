@@ -0,0 +1,351 @@
+//! Crash reports surviving the reset.
+//!
+//! The panic handler (`panic_probe`, see `panic/`), the defmt panic handler and the HardFault
+//! handler record where the firmware stopped in the `.retained` RAM section of `memory.x`, which
+//! the startup code and the bootloader leave alone, then reset. A panic keeps its file, line and
+//! the start of its message, a HardFault its registers. At the next boot the report is shown on
+//! the screen and moved to the crash log flash page, which keeps the last few ones.
+//!
+//! `unwrap!` is the one of defmt, but panics with the location of the call: the `unwrap!` of
+//! defmt only reaches a panic handler without it.
+
+use core::{
+    fmt::{self, Write as _},
+    mem::MaybeUninit,
+    panic::PanicInfo,
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+use embassy_nrf::nvmc::PAGE_SIZE;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+use crate::{config::CRASH_LOG_ADDR, flash::shared_flash};
+
+/// Marks a valid report, "CRS2", the reports of "CRSH" had no location.
+const CRASH_MAGIC: u32 = u32::from_le_bytes(*b"CRS2");
+/// End of the file of a panic, from `src/` for the files of the firmware.
+const FILE_BYTES: usize = 24;
+/// Start of the message of a panic.
+const MESSAGE_BYTES: usize = 32;
+const HEADER_WORDS: usize = 6;
+const CRASH_WORDS: usize = HEADER_WORDS + (FILE_BYTES + MESSAGE_BYTES) / 4;
+const CRASH_BYTES: usize = 4 * CRASH_WORDS;
+/// Reports kept in the crash log page, the oldest one is dropped first.
+const CRASH_LOG_LEN: usize = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CrashKind {
+    /// Rust panic, from `unwrap!` too, with its location and message.
+    Panic = 0,
+    /// `defmt::panic!` or `expect!`, whose message only goes to a debug probe.
+    DefmtPanic = 1,
+    HardFault = 2,
+}
+
+#[derive(Clone, Copy)]
+pub struct CrashReport {
+    pub kind: CrashKind,
+    /// Registers of a HardFault, resolved with `addr2line -e <elf> <pc>`.
+    pub pc: u32,
+    pub lr: u32,
+    /// Configurable Fault Status Register, the cause of the fault.
+    pub cfsr: u32,
+    /// Line of a panic.
+    pub line: u32,
+    file: [u8; FILE_BYTES],
+    message: [u8; MESSAGE_BYTES],
+}
+
+/// Text of `bytes`, up to the padding and without a character cut at the end.
+fn text(bytes: &[u8]) -> &str {
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    match core::str::from_utf8(&bytes[..len]) {
+        Ok(text) => text,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+    }
+}
+
+/// Writes the start of a text, up to the capacity of the bytes.
+struct TextWriter<'a> {
+    bytes: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for TextWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let end = self.len + c.len_utf8();
+            if end > self.bytes.len() {
+                break;
+            }
+            c.encode_utf8(&mut self.bytes[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
+
+impl CrashReport {
+    fn new(kind: CrashKind) -> Self {
+        Self {
+            kind,
+            pc: 0,
+            lr: 0,
+            cfsr: 0,
+            line: 0,
+            file: [0; FILE_BYTES],
+            message: [0; MESSAGE_BYTES],
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self.kind {
+            CrashKind::Panic => "panique",
+            CrashKind::DefmtPanic => "defmt",
+            CrashKind::HardFault => "HardFault",
+        }
+    }
+
+    pub fn file(&self) -> &str {
+        text(&self.file)
+    }
+
+    pub fn message(&self) -> &str {
+        text(&self.message)
+    }
+
+    fn to_words(self) -> [u32; CRASH_WORDS] {
+        let mut words = [0; CRASH_WORDS];
+        words[..HEADER_WORDS].copy_from_slice(&[
+            CRASH_MAGIC,
+            self.kind as u32,
+            self.pc,
+            self.lr,
+            self.cfsr,
+            self.line,
+        ]);
+        let texts = self
+            .file
+            .chunks_exact(4)
+            .chain(self.message.chunks_exact(4));
+        for (word, bytes) in words[HEADER_WORDS..].iter_mut().zip(texts) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        words
+    }
+
+    fn from_words(words: [u32; CRASH_WORDS]) -> Option<Self> {
+        if words[0] != CRASH_MAGIC {
+            return None;
+        }
+        let mut report = Self::new(match words[1] {
+            0 => CrashKind::Panic,
+            1 => CrashKind::DefmtPanic,
+            2 => CrashKind::HardFault,
+            _ => return None,
+        });
+        report.pc = words[2];
+        report.lr = words[3];
+        report.cfsr = words[4];
+        report.line = words[5];
+        let texts = report
+            .file
+            .chunks_exact_mut(4)
+            .chain(report.message.chunks_exact_mut(4));
+        for (bytes, word) in texts.zip(&words[HEADER_WORDS..]) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        Some(report)
+    }
+}
+
+/// Report of the crash which caused the last reset, garbage after a power-on.
+#[link_section = ".retained"]
+static mut RETAINED_CRASH: MaybeUninit<[u32; CRASH_WORDS]> = MaybeUninit::uninit();
+/// Set by the panic handlers, so that the fault they trigger keeps their report.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// Report taken at boot, until it is written to the crash log.
+static UNSAVED_REPORT: Signal<CriticalSectionRawMutex, CrashReport> = Signal::new();
+
+fn retain(report: CrashReport) {
+    let retained = unsafe { addr_of_mut!(RETAINED_CRASH) }.cast::<u32>();
+    for (index, word) in report.to_words().into_iter().enumerate() {
+        unsafe { retained.add(index).write_volatile(word) };
+    }
+}
+
+fn take_retained() -> Option<CrashReport> {
+    let retained = unsafe { addr_of_mut!(RETAINED_CRASH) }.cast::<u32>();
+    let words = core::array::from_fn(|index| unsafe { retained.add(index).read_volatile() });
+    unsafe { retained.write_volatile(0) };
+    CrashReport::from_words(words)
+}
+
+/// Retain the report of a panic, then fault: a debug probe stops there, else the HardFault
+/// handler resets the half.
+fn panic_with(report: CrashReport) -> ! {
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        retain(report);
+    }
+    cortex_m::asm::udf()
+}
+
+/// The panic handler, called by the one of `panic/`.
+#[no_mangle]
+fn urchin_panic(info: &PanicInfo) -> ! {
+    defmt::error!("{}", defmt::Display2Format(info));
+    let mut report = CrashReport::new(CrashKind::Panic);
+    if let Some(location) = info.location() {
+        let file = location.file();
+        let start = (file.len().saturating_sub(FILE_BYTES)..file.len())
+            .find(|&index| file.is_char_boundary(index))
+            .unwrap_or(file.len());
+        let _ = TextWriter {
+            bytes: &mut report.file,
+            len: 0,
+        }
+        .write_str(&file[start..]);
+        report.line = location.line();
+    }
+    let _ = write!(
+        TextWriter {
+            bytes: &mut report.message,
+            len: 0,
+        },
+        "{}",
+        info.message()
+    );
+    panic_with(report)
+}
+
+#[defmt::panic_handler]
+fn defmt_panic() -> ! {
+    panic_with(CrashReport::new(CrashKind::DefmtPanic))
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    if !PANICKING.load(Ordering::Relaxed) {
+        let mut report = CrashReport::new(CrashKind::HardFault);
+        report.pc = frame.pc();
+        report.lr = frame.lr();
+        report.cfsr = (*SCB::PTR).cfsr.read();
+        retain(report);
+    }
+    SCB::sys_reset()
+}
+
+/// What `unwrap!` takes: an `Option`, or a `Result` whose error is logged.
+pub trait Unwrap {
+    type Value;
+    fn value(self) -> Option<Self::Value>;
+}
+
+impl<T> Unwrap for Option<T> {
+    type Value = T;
+
+    fn value(self) -> Option<T> {
+        self
+    }
+}
+
+impl<T, E: defmt::Format> Unwrap for Result<T, E> {
+    type Value = T;
+
+    fn value(self) -> Option<T> {
+        self.inspect_err(|e| defmt::error!("unwrap failed: {}", e))
+            .ok()
+    }
+}
+
+/// `defmt::unwrap!`, with the location of the call in the crash report.
+#[macro_export]
+macro_rules! unwrap {
+    ($value:expr) => {
+        match $crate::crash::Unwrap::value($value) {
+            Some(value) => value,
+            None => ::core::panic!(concat!("unwrap ", stringify!($value))),
+        }
+    };
+}
+
+/// Reports of the last crashes from the crash log page, the most recent first.
+async fn crash_log() -> [Option<CrashReport>; CRASH_LOG_LEN] {
+    let mut bytes = [0; CRASH_LOG_LEN * CRASH_BYTES];
+    if let Err(e) = shared_flash().read(CRASH_LOG_ADDR, &mut bytes).await {
+        defmt::error!("Failed to read the crash log: {}", e);
+        return [None; CRASH_LOG_LEN];
+    }
+    core::array::from_fn(|index| {
+        let report = &bytes[index * CRASH_BYTES..][..CRASH_BYTES];
+        CrashReport::from_words(core::array::from_fn(|word| {
+            let bytes = &report[4 * word..];
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        }))
+    })
+}
+
+async fn save_to_crash_log(report: CrashReport) {
+    let mut log = crash_log().await;
+    log.rotate_right(1);
+    log[0] = Some(report);
+
+    let mut bytes = [0xFF; CRASH_LOG_LEN * CRASH_BYTES];
+    for (report, chunk) in log.iter().zip(bytes.chunks_exact_mut(CRASH_BYTES)) {
+        let Some(report) = report else {
+            continue;
+        };
+        for (word, word_bytes) in report.to_words().into_iter().zip(chunk.chunks_exact_mut(4)) {
+            word_bytes.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+    let mut flash = shared_flash();
+    if let Err(e) = flash
+        .erase(CRASH_LOG_ADDR, CRASH_LOG_ADDR + PAGE_SIZE as u32)
+        .await
+    {
+        defmt::error!("Failed to erase the crash log: {}", e);
+        return;
+    }
+    if let Err(e) = flash.write(CRASH_LOG_ADDR, &bytes).await {
+        defmt::error!("Failed to write the crash log: {}", e);
+    }
+}
+
+/// Take the report of the crash which caused the last reset, if any.
+///
+/// It goes to the crash log with `save_crash_report`, once the flash is shared.
+pub fn take_crash_report() -> Option<CrashReport> {
+    let report = take_retained()?;
+    match report.kind {
+        CrashKind::HardFault => defmt::error!(
+            "Reset after a HardFault: pc={=u32:#010x} lr={=u32:#010x} cfsr={=u32:#010x}",
+            report.pc,
+            report.lr,
+            report.cfsr
+        ),
+        _ => defmt::error!(
+            "Reset after a crash ({}): {}:{} {}",
+            report.label(),
+            report.file(),
+            report.line,
+            report.message()
+        ),
+    }
+    UNSAVED_REPORT.signal(report);
+    Some(report)
+}
+
+/// Write the report taken at boot to the crash log, if not done yet.
+pub async fn save_crash_report() {
+    if let Some(report) = UNSAVED_REPORT.try_take() {
+        save_to_crash_log(report).await;
+    }
+}
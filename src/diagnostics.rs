@@ -0,0 +1,145 @@
+use core::fmt::Write as _;
+
+use embassy_time::Duration;
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_6X10, FONT_7X13_BOLD, FONT_9X15},
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point},
+    text::Text,
+    Drawable,
+};
+use heapless::{String, Vec};
+
+use crate::{
+    config::{BUILD_DATE, FIRMWARE_VERSION, GIT_HASH, KEYBOARD_TOML_HASH},
+    crash::{CrashKind, CrashReport},
+    unwrap,
+    watchdog::ResetReason,
+};
+
+/// Draw the raw battery measurement, to calibrate the discharge curve.
+pub fn draw_battery_diagnostics<D>(target: &mut D, percent: u8, millivolts: u16)
+where
+    D: DrawTarget<Color = BinaryColor>,
+    D::Error: defmt::Format,
+{
+    let title_style = MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::Off);
+    unwrap!(Text::new("BATTERIE", Point { x: 2, y: 14 }, title_style).draw(target));
+
+    let value_style = MonoTextStyle::new(&FONT_9X15, BinaryColor::Off);
+    let mut percent_buffer = itoa::Buffer::new();
+    unwrap!(Text::new(
+        percent_buffer.format(percent),
+        Point { x: 2, y: 40 },
+        value_style
+    )
+    .draw(target));
+    unwrap!(Text::new("%", Point { x: 38, y: 40 }, value_style).draw(target));
+
+    let mut millivolts_buffer = itoa::Buffer::new();
+    unwrap!(Text::new(
+        millivolts_buffer.format(millivolts),
+        Point { x: 2, y: 60 },
+        value_style
+    )
+    .draw(target));
+    unwrap!(Text::new("mV", Point { x: 38, y: 60 }, value_style).draw(target));
+}
+
+/// Characters of a line of the crash report.
+const CRASH_COLUMNS: usize = 11;
+/// Lines of the crash report below its label.
+const CRASH_LINES: usize = 9;
+/// A line of the crash report, in bytes for any characters.
+type CrashLine = String<{ 4 * CRASH_COLUMNS }>;
+
+/// Draw the crash which caused the last reset: the file, line and message of a panic, or the
+/// registers of a HardFault, resolved with `addr2line`.
+pub fn draw_crash_report<D>(target: &mut D, report: &CrashReport)
+where
+    D: DrawTarget<Color = BinaryColor>,
+    D::Error: defmt::Format,
+{
+    let title_style = MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::Off);
+    unwrap!(Text::new("PLANTAGE", Point { x: 2, y: 14 }, title_style).draw(target));
+
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::Off);
+    unwrap!(Text::new(report.label(), Point { x: 1, y: 32 }, style).draw(target));
+    let mut lines = Vec::<CrashLine, CRASH_LINES>::new();
+    match report.kind {
+        CrashKind::HardFault => {
+            for (name, value) in [("pc", report.pc), ("lr", report.lr), ("cf", report.cfsr)] {
+                let mut line = CrashLine::new();
+                unwrap!(write!(line, "{name} {value:08x}").ok());
+                let _ = lines.push(line);
+            }
+        }
+        CrashKind::Panic => {
+            let file = report.file();
+            let file_name = file.rsplit('/').next().unwrap_or(file);
+            let mut line = CrashLine::new();
+            unwrap!(write!(line, "l. {}", report.line).ok());
+            for line in columns(file_name)
+                .chain(core::iter::once(line.as_str()))
+                .chain(columns(report.message()))
+            {
+                let _ = lines.push(unwrap!(CrashLine::try_from(line).ok()));
+            }
+        }
+        // Only the probe got the message
+        CrashKind::DefmtPanic => {}
+    }
+    for (index, line) in lines.iter().enumerate() {
+        let y = 46 + 12 * index as i32;
+        unwrap!(Text::new(line, Point { x: 1, y }, style).draw(target));
+    }
+}
+
+/// Split a text into lines of `CRASH_COLUMNS` characters.
+fn columns(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    core::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest
+            .char_indices()
+            .nth(CRASH_COLUMNS)
+            .map_or(rest.len(), |(index, _)| index);
+        let (line, tail) = rest.split_at(end);
+        rest = tail;
+        Some(line)
+    })
+}
+
+/// How long the boot splash stays on the screen.
+pub const SPLASH_DURATION: Duration = Duration::from_secs(2);
+
+/// Draw the boot splash: the half, the build running on it and the reason of the reset.
+pub fn draw_boot_splash<D>(target: &mut D, half: &str, reset_reason: ResetReason)
+where
+    D: DrawTarget<Color = BinaryColor>,
+    D::Error: defmt::Format,
+{
+    let title_style = MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::Off);
+    unwrap!(Text::new("URCHIN", Point { x: 2, y: 14 }, title_style).draw(target));
+
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::Off);
+    let mut version = String::<11>::new();
+    unwrap!(write!(version, "v{FIRMWARE_VERSION}").ok());
+    let mut keyboard_toml = String::<11>::new();
+    unwrap!(write!(keyboard_toml, "kb {KEYBOARD_TOML_HASH}").ok());
+    let lines = [half, &version, GIT_HASH, BUILD_DATE, &keyboard_toml];
+    for (index, line) in lines.into_iter().enumerate() {
+        let y = 30 + 12 * index as i32;
+        unwrap!(Text::new(line, Point { x: 1, y }, style).draw(target));
+    }
+
+    if reset_reason.is_notable() {
+        unwrap!(Text::new("reset", Point { x: 1, y: 98 }, style).draw(target));
+        unwrap!(Text::new(reset_reason.label(), Point { x: 1, y: 110 }, style).draw(target));
+    }
+}
@@ -0,0 +1,30 @@
+//! Bring-up of the display described by `[display]`, the same on both halves.
+
+use embassy_nrf::{
+    gpio::{Level, Output, OutputDrive, Pin},
+    spim, Peri,
+};
+use sharp_memory_display::MODE;
+
+use crate::{
+    config::{DisplayIrqs, DisplaySpi},
+    nice_view::NiceView,
+};
+
+/// Set up the SPI bus and the chip select of the display, then clear it.
+///
+/// The peripherals are the ones of `config::display_peripherals!`.
+pub fn init_display(
+    spi: Peri<'static, DisplaySpi>,
+    sck: Peri<'static, impl Pin>,
+    mosi: Peri<'static, impl Pin>,
+    cs: Peri<'static, impl Pin>,
+) -> NiceView<'static> {
+    let mut config = spim::Config::default();
+    config.mode = MODE;
+    let spi = spim::Spim::new_txonly(spi, DisplayIrqs, sck, mosi, config);
+    let cs = Output::new(cs, Level::High, OutputDrive::Standard);
+    let mut display = NiceView::new(spi, cs);
+    display.clear();
+    display
+}
@@ -0,0 +1,6 @@
+use nrf_mpsl::Flash;
+
+/// Handle of the flash of RMK's storage, for the firmware pages outside of its region.
+pub fn shared_flash() -> Flash {
+    Flash::shared()
+}
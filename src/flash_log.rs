@@ -0,0 +1,250 @@
+//! Events of the log controller kept in a ring of flash pages, to find out what happened while
+//! nobody was watching the log page.
+//!
+//! Each page starts with a header, the magic and a sequence number increasing with each page
+//! started, followed by 8 byte records: a tag, up to 3 bytes of payload and the milliseconds
+//! since boot. Records still erased are all `0xFF`, and each boot starts with a `Boot` record
+//! holding the `ResetReason`.
+//! The `event-log` tool decodes them from a flash dump, its tags must follow `Tag`.
+//!
+//! The records wait in RAM and are written in batches, through the flash shared with RMK's
+//! storage: the ones of the last `FLUSH_INTERVAL` are lost if the half resets.
+
+use embassy_nrf::nvmc::PAGE_SIZE;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::Vec;
+use rmk::{ble::BleState, event::ControllerEvent};
+
+use crate::{
+    config::{EVENT_LOG_FLASH_ADDR, EVENT_LOG_FLASH_PAGES},
+    flash::shared_flash,
+    watchdog::ResetReason,
+};
+
+/// Marks a page of the ring, "ULOG".
+const FLASH_LOG_MAGIC: u32 = u32::from_le_bytes(*b"ULOG");
+const HEADER_BYTES: u32 = 8;
+const RECORD_BYTES: u32 = 8;
+const RECORDS_PER_PAGE: u32 = (PAGE_SIZE as u32 - HEADER_BYTES) / RECORD_BYTES;
+const ERASED_TAG: u8 = 0xFF;
+/// Records kept in RAM, written once there are as many.
+const PENDING_RECORDS: usize = 32;
+/// Longest wait of a record in RAM.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy)]
+enum Tag {
+    Boot = 0,
+    Battery = 1,
+    ChargingState = 2,
+    Layer = 3,
+    ConnectionType = 4,
+    SplitPeripheral = 5,
+    SplitCentral = 6,
+    Sleep = 7,
+    BleState = 8,
+    BleProfile = 9,
+    ClearPeer = 10,
+}
+
+/// Binary record of an event, `None` for the events which are not kept.
+///
+/// Key events are left out, they would wear the flash out.
+fn encode(event: &ControllerEvent) -> Option<(Tag, [u8; 3])> {
+    Some(match *event {
+        ControllerEvent::Battery(percent) => (Tag::Battery, [percent, 0, 0]),
+        ControllerEvent::ChargingState(charging) => (Tag::ChargingState, [charging as u8, 0, 0]),
+        ControllerEvent::Layer(layer) => (Tag::Layer, [layer, 0, 0]),
+        ControllerEvent::ConnectionType(connection_type) => {
+            (Tag::ConnectionType, [connection_type, 0, 0])
+        }
+        ControllerEvent::SplitPeripheral(id, connected) => {
+            (Tag::SplitPeripheral, [id as u8, connected as u8, 0])
+        }
+        ControllerEvent::SplitCentral(connected) => (Tag::SplitCentral, [connected as u8, 0, 0]),
+        ControllerEvent::Sleep(sleeping) => (Tag::Sleep, [sleeping as u8, 0, 0]),
+        ControllerEvent::BleState(profile, state) => {
+            let state = match state {
+                BleState::None => 0,
+                BleState::Advertising => 1,
+                BleState::Connected => 2,
+            };
+            (Tag::BleState, [profile, state, 0])
+        }
+        ControllerEvent::BleProfile(profile) => (Tag::BleProfile, [profile, 0, 0]),
+        ControllerEvent::ClearPeer => (Tag::ClearPeer, [0; 3]),
+        _ => return None,
+    })
+}
+
+fn page_addr(page: u32) -> u32 {
+    EVENT_LOG_FLASH_ADDR + page * PAGE_SIZE as u32
+}
+
+/// Where the next record goes in the ring.
+struct Position {
+    /// Page being written, in `0..EVENT_LOG_FLASH_PAGES`.
+    page: u32,
+    sequence: u32,
+    /// Next record of the page, `RECORDS_PER_PAGE` once it is full.
+    next_record: u32,
+}
+
+impl Position {
+    /// Find where the previous session stopped.
+    async fn find() -> Self {
+        let mut flash = shared_flash();
+        let mut last_page = None;
+        for page in 0..EVENT_LOG_FLASH_PAGES {
+            let mut header = [0; HEADER_BYTES as usize];
+            if let Err(e) = flash.read(page_addr(page), &mut header).await {
+                defmt::error!("Failed to read the flash event log: {}", e);
+                continue;
+            }
+            let [m0, m1, m2, m3, s0, s1, s2, s3] = header;
+            let sequence = u32::from_le_bytes([s0, s1, s2, s3]);
+            if u32::from_le_bytes([m0, m1, m2, m3]) == FLASH_LOG_MAGIC
+                && last_page.is_none_or(|(_, last_sequence)| sequence > last_sequence)
+            {
+                last_page = Some((page, sequence));
+            }
+        }
+
+        match last_page {
+            Some((page, sequence)) => {
+                let mut next_record = RECORDS_PER_PAGE;
+                for record in 0..RECORDS_PER_PAGE {
+                    let mut tag = [0];
+                    let addr = page_addr(page) + HEADER_BYTES + record * RECORD_BYTES;
+                    if flash.read(addr, &mut tag).await.is_ok() && tag[0] == ERASED_TAG {
+                        next_record = record;
+                        break;
+                    }
+                }
+                Self {
+                    page,
+                    sequence,
+                    next_record,
+                }
+            }
+            // Empty ring, the first record starts page 0
+            None => Self {
+                page: EVENT_LOG_FLASH_PAGES - 1,
+                sequence: 0,
+                next_record: RECORDS_PER_PAGE,
+            },
+        }
+    }
+
+    /// Erase the oldest page of the ring to continue the log there.
+    async fn start_next_page(&mut self) -> bool {
+        let page = (self.page + 1) % EVENT_LOG_FLASH_PAGES;
+        let sequence = self.sequence.wrapping_add(1);
+        let mut flash = shared_flash();
+        if let Err(e) = flash
+            .erase(page_addr(page), page_addr(page) + PAGE_SIZE as u32)
+            .await
+        {
+            defmt::error!("Failed to erase the flash event log: {}", e);
+            return false;
+        }
+        let [m0, m1, m2, m3] = FLASH_LOG_MAGIC.to_le_bytes();
+        let [s0, s1, s2, s3] = sequence.to_le_bytes();
+        let header = [m0, m1, m2, m3, s0, s1, s2, s3];
+        if let Err(e) = flash.write(page_addr(page), &header).await {
+            defmt::error!("Failed to write the flash event log: {}", e);
+            return false;
+        }
+        self.page = page;
+        self.sequence = sequence;
+        self.next_record = 0;
+        true
+    }
+}
+
+/// Writer of the flash event log.
+pub struct FlashLog {
+    /// Found at the first flush, the flash is not read before.
+    position: Option<Position>,
+    /// Records not written yet, the oldest first.
+    pending: Vec<u8, { PENDING_RECORDS * RECORD_BYTES as usize }>,
+    /// When the oldest pending record must be written.
+    flush_deadline: Option<Instant>,
+}
+
+impl FlashLog {
+    /// Start a new session, after the one of the previous boot.
+    pub fn new(reset_reason: ResetReason) -> Self {
+        let mut flash_log = Self {
+            position: None,
+            pending: Vec::new(),
+            flush_deadline: None,
+        };
+        flash_log.push(Tag::Boot, [reset_reason as u8, 0, 0]);
+        flash_log
+    }
+
+    /// Keep an event, if it has a binary record.
+    ///
+    /// The records are written when enough of them are waiting, and before the half sleeps.
+    pub async fn record(&mut self, event: &ControllerEvent) {
+        let Some((tag, payload)) = encode(event) else {
+            return;
+        };
+        self.push(tag, payload);
+        if self.pending.is_full() || matches!(event, ControllerEvent::Sleep(true)) {
+            self.flush().await;
+        }
+    }
+
+    fn push(&mut self, tag: Tag, payload: [u8; 3]) {
+        let [t0, t1, t2, t3] = (Instant::now().as_millis() as u32).to_le_bytes();
+        let [p0, p1, p2] = payload;
+        if self
+            .pending
+            .extend_from_slice(&[tag as u8, p0, p1, p2, t0, t1, t2, t3])
+            .is_err()
+        {
+            defmt::warn!("Flash event log full, dropping a record");
+            return;
+        }
+        self.flush_deadline
+            .get_or_insert_with(|| Instant::now() + FLUSH_INTERVAL);
+    }
+
+    /// Wait until the pending records must be written, forever if there are none.
+    pub async fn flush_due(&self) {
+        match self.flush_deadline {
+            Some(deadline) => Timer::at(deadline).await,
+            None => core::future::pending().await,
+        }
+    }
+
+    /// Write the pending records, a page at a time.
+    ///
+    /// A record is written once, even if it failed.
+    pub async fn flush(&mut self) {
+        self.flush_deadline = None;
+        let position = match &mut self.position {
+            Some(position) => position,
+            None => self.position.insert(Position::find().await),
+        };
+        let mut rest = &self.pending[..];
+        while !rest.is_empty() {
+            if position.next_record == RECORDS_PER_PAGE && !position.start_next_page().await {
+                break;
+            }
+            let free = (RECORDS_PER_PAGE - position.next_record) * RECORD_BYTES;
+            let (bytes, tail) = rest.split_at(rest.len().min(free as usize));
+            let addr =
+                page_addr(position.page) + HEADER_BYTES + position.next_record * RECORD_BYTES;
+            position.next_record += bytes.len() as u32 / RECORD_BYTES;
+            rest = tail;
+            if let Err(e) = shared_flash().write(addr, bytes).await {
+                defmt::error!("Failed to write the flash event log: {}", e);
+            }
+        }
+        self.pending.clear();
+    }
+}
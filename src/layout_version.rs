@@ -0,0 +1,90 @@
+//! Reset of the keymap stored by RMK when the flashed default keymap changes.
+//!
+//! RMK keeps the keymap changed from Vial in its storage, and loads it at boot over the keymap of
+//! `keyboard.toml` (`clear_layout = false`). The fingerprint of the keymap the stored one started
+//! from is kept in the layout flash page: when a firmware with another keymap boots, the keymap of
+//! RMK's storage is overwritten with the new default one before RMK loads it, like the reset of
+//! Vial, and the half restarts to tell it. The rest of RMK's storage, with the BLE bonds, stays.
+
+use embassy_nrf::nvmc::PAGE_SIZE;
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use rmk::{
+    config::{BehaviorConfig, StorageConfig},
+    storage::Storage,
+    types::action::KeyAction,
+};
+
+use crate::{
+    config::{LAYOUT_ADDR, LAYOUT_FINGERPRINT},
+    flash::shared_flash,
+    watchdog::{restart, ResetReason},
+};
+
+/// Fingerprint read from an erased page, before the first one is stored.
+const NO_FINGERPRINT: u32 = u32::MAX;
+
+async fn write_fingerprint() {
+    let mut flash = shared_flash();
+    if let Err(e) = flash
+        .erase(LAYOUT_ADDR, LAYOUT_ADDR + PAGE_SIZE as u32)
+        .await
+    {
+        defmt::error!("Failed to erase the layout fingerprint: {}", e);
+        return;
+    }
+    if let Err(e) = flash
+        .write(LAYOUT_ADDR, &LAYOUT_FINGERPRINT.to_le_bytes())
+        .await
+    {
+        defmt::error!("Failed to write the layout fingerprint: {}", e);
+    }
+}
+
+/// Reset the keymap of RMK's storage and restart if it was written from another default keymap.
+///
+/// Must run before RMK opens its storage. Without a fingerprint yet, the stored keymap is kept:
+/// it is the one of the firmware before the fingerprints, or none.
+pub async fn check_layout_fingerprint<
+    const ROW: usize,
+    const COL: usize,
+    const NUM_LAYER: usize,
+>(
+    keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
+    storage_config: &StorageConfig,
+    behavior_config: &BehaviorConfig,
+) {
+    let mut bytes = [0; 4];
+    if let Err(e) = shared_flash().read(LAYOUT_ADDR, &mut bytes).await {
+        defmt::error!("Failed to read the layout fingerprint: {}", e);
+        return;
+    }
+    let stored = u32::from_le_bytes(bytes);
+    if stored == LAYOUT_FINGERPRINT {
+        return;
+    }
+    if stored == NO_FINGERPRINT {
+        write_fingerprint().await;
+        return;
+    }
+    defmt::warn!(
+        "Default keymap changed ({=u32:#010x} to {=u32:#010x}), clearing the stored keymap",
+        stored,
+        LAYOUT_FINGERPRINT
+    );
+    // RMK's own reset at boot, which overwrites the layout items and keeps the rest
+    let clear_layout = StorageConfig {
+        clear_layout: true,
+        ..*storage_config
+    };
+    Storage::<_, ROW, COL, NUM_LAYER>::new(
+        shared_flash(),
+        keymap,
+        &None,
+        &clear_layout,
+        behavior_config,
+    )
+    .await;
+    // Only written once the keymap is cleared: a reset before retries at the next boot
+    write_fingerprint().await;
+    restart(ResetReason::NewKeymap)
+}
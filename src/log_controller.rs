@@ -1,127 +1,316 @@
-use defmt::unwrap;
+use core::cell::RefCell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    pubsub::WaitResult,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
 use embedded_graphics::{
     mono_font::{ascii::FONT_4X6, MonoFont, MonoTextStyle},
     pixelcolor::BinaryColor,
-    prelude::Point,
-    text::Text,
+    prelude::{DrawTarget, Point},
+    text::{Alignment, Text},
     Drawable,
 };
-use heapless::{CapacityError, String};
-use rmk::{ble::BleState, channel::ControllerSub, controller::Controller, event::ControllerEvent};
+use heapless::Deque;
+use rmk::{channel::ControllerSub, controller::Controller, event::ControllerEvent};
+use urchin_log_format::{
+    format_elapsed, format_event, wrap, LogEntry, TapHoldResolver, LOG_TIME_COLUMNS,
+};
 
-use crate::nice_view::NiceView;
+use crate::{
+    config::{LOG_CATEGORIES, LOG_CHANGES_ONLY, MORSE_HOLD_TIMEOUT_MS},
+    flash_log::FlashLog,
+    unwrap,
+    watchdog::ResetReason,
+};
 
 const LOG_LINE_HEIGHT: usize = 6;
 const LOG_STYLE: MonoFont<'static> = FONT_4X6;
 const LOG_LINES: usize = 25; // 160 = 6 * 25 + 5
-const LOG_COLUMNS: usize = 16; // 68 = 4 * 16 + 4
-type LogEntry = String<LOG_COLUMNS>;
-struct LogScreenController<'a> {
-    sub: ControllerSub,
-    display: NiceView<'a>,
-    log_history: [Option<LogEntry>; LOG_LINES],
+/// Events kept in memory, the log page scrolls through them.
+const LOG_HISTORY: usize = 128;
+
+struct LogRecord {
+    time: Instant,
+    entry: LogEntry,
 }
 
-impl LogScreenController<'_> {
-    fn log_event(&mut self, event: ControllerEvent) -> Result<(), CapacityError> {
-        match event {
-            ControllerEvent::Battery(l) => {
-                let mut entry = LogEntry::try_from("bat")?;
-                let mut buffer = itoa::Buffer::new();
-                entry.push_str(buffer.format(l))?;
-                self.log(entry);
-            }
-            ControllerEvent::ChargingState(s) => {
-                self.log(LogEntry::try_from(if s {
-                    "charge oui"
-                } else {
-                    "charge non"
-                })?);
-            }
-            ControllerEvent::Layer(l) => {
-                self.log(LogEntry::try_from(match l {
-                    0 => "layer base",
-                    1 => "layer nav",
-                    2 => "layer prog",
-                    3 => "layer peri",
-                    _ => "",
-                })?);
-            }
-            ControllerEvent::ConnectionType(t) => {
-                self.log(LogEntry::try_from(if t == 0 {
-                    "conn USB"
-                } else {
-                    "conn BLE"
-                })?);
-            }
-            ControllerEvent::SplitPeripheral(_, c) => {
-                self.log(String::try_from(if c { "peri oui" } else { "peri non" })?);
-            }
-            ControllerEvent::SplitCentral(c) => {
-                self.log(String::try_from(if c { "peri oui" } else { "peri non" })?);
-            }
-            ControllerEvent::Sleep(s) => {
-                self.log(String::try_from(if s { "dodo oui" } else { "dodo non" })?);
-            }
-            ControllerEvent::BleState(p, s) => {
-                let mut entry = LogEntry::try_from(match p {
-                    0 => "prof 0 ",
-                    1 => "prof 1 ",
-                    2 => "prof 2 ",
-                    _ => "",
-                })?;
-                entry.push_str(match s {
-                    BleState::Advertising => "advr",
-                    BleState::Connected => "conn",
-                    BleState::None => "none",
-                })?;
-                self.log(entry);
-            }
-            ControllerEvent::BleProfile(p) => {
-                self.log(LogEntry::try_from(match p {
-                    0 => "prof 0 ",
-                    1 => "prof 1 ",
-                    2 => "prof 2 ",
-                    _ => "",
-                })?);
-            }
-            ControllerEvent::ClearPeer => {
-                self.log(LogEntry::try_from("clear peer")?);
+/// Families of controller events, which can be filtered out of the log.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogCategory {
+    Power = 1 << 0,
+    Ble = 1 << 1,
+    Split = 1 << 2,
+    Layer = 1 << 3,
+    Keys = 1 << 4,
+    Sleep = 1 << 5,
+}
+
+impl LogCategory {
+    fn of(event: &ControllerEvent) -> Option<Self> {
+        Some(match event {
+            ControllerEvent::Battery(_) | ControllerEvent::ChargingState(_) => LogCategory::Power,
+            ControllerEvent::BleState(..)
+            | ControllerEvent::BleProfile(_)
+            | ControllerEvent::ClearPeer
+            | ControllerEvent::ConnectionType(_) => LogCategory::Ble,
+            ControllerEvent::SplitPeripheral(..) | ControllerEvent::SplitCentral(_) => {
+                LogCategory::Split
             }
-            _ => {
-                return Ok(());
+            ControllerEvent::Layer(_) => LogCategory::Layer,
+            ControllerEvent::Key(..) => LogCategory::Keys,
+            ControllerEvent::Sleep(_) => LogCategory::Sleep,
+            _ => return None,
+        })
+    }
+}
+
+/// Which events make it to the log.
+#[derive(Clone, Copy, PartialEq)]
+pub struct LogFilter {
+    categories: u8,
+    /// Skip the events repeating the previous value, like the same battery level.
+    pub changes_only: bool,
+}
+
+impl LogFilter {
+    /// Filter at boot, from the `[log]` section of `keyboard.toml`.
+    const DEFAULT: LogFilter = LogFilter {
+        categories: LOG_CATEGORIES,
+        changes_only: LOG_CHANGES_ONLY,
+    };
+
+    pub fn contains(self, category: LogCategory) -> bool {
+        self.categories & category as u8 != 0
+    }
+
+    pub fn toggle(&mut self, category: LogCategory) {
+        self.categories ^= category as u8;
+    }
+}
+
+/// Slot of the last value of an event, for the events carrying a value which can repeat.
+fn value_slot(event: &ControllerEvent) -> Option<usize> {
+    Some(match event {
+        ControllerEvent::Battery(_) => 0,
+        ControllerEvent::ChargingState(_) => 1,
+        ControllerEvent::Layer(_) => 2,
+        ControllerEvent::ConnectionType(_) => 3,
+        ControllerEvent::SplitPeripheral(..) => 4,
+        ControllerEvent::SplitCentral(_) => 5,
+        ControllerEvent::Sleep(_) => 6,
+        ControllerEvent::BleState(..) => 7,
+        ControllerEvent::BleProfile(_) => 8,
+        _ => return None,
+    })
+}
+const VALUE_SLOTS: usize = 9;
+
+/// Events logged by the `LogController`, whatever the screen shows.
+pub static EVENT_LOG: Mutex<CriticalSectionRawMutex, RefCell<EventLog>> =
+    Mutex::new(RefCell::new(EventLog::new()));
+/// Signaled each time an event is added to `EVENT_LOG`.
+pub static EVENT_LOG_UPDATED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Last controller events, formatted for the log page.
+pub struct EventLog {
+    /// The most recent event first.
+    log_history: Deque<LogRecord, LOG_HISTORY>,
+    /// Events missed by the `LogController`.
+    dropped: u32,
+    filter: LogFilter,
+    /// Last entry of each event carrying a value, see `value_slot`.
+    last_values: [Option<LogEntry>; VALUE_SLOTS],
+    tap_holds: TapHoldResolver,
+}
+
+impl EventLog {
+    const fn new() -> Self {
+        Self {
+            log_history: Deque::new(),
+            dropped: 0,
+            filter: LogFilter::DEFAULT,
+            last_values: [const { None }; VALUE_SLOTS],
+            tap_holds: TapHoldResolver::new(Duration::from_millis(MORSE_HOLD_TIMEOUT_MS as u64)),
+        }
+    }
+
+    pub fn filter(&self) -> LogFilter {
+        self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: LogFilter) {
+        self.filter = filter;
+    }
+
+    /// Lines at the top of the page showing the log state instead of events.
+    fn header_lines(&self) -> usize {
+        (self.dropped > 0) as usize
+    }
+
+    /// Screen lines taken by the history, once wrapped.
+    fn line_count(&self) -> usize {
+        self.log_history
+            .iter()
+            .map(|record| wrap(&record.entry).count())
+            .sum()
+    }
+
+    /// Lines of the page available to the history.
+    fn visible_lines(&self) -> usize {
+        LOG_LINES - self.header_lines()
+    }
+
+    /// Largest scroll offset keeping the screen full, in lines.
+    pub fn max_scroll(&self) -> usize {
+        self.line_count().saturating_sub(self.visible_lines())
+    }
+
+    /// Log an event if the filter lets it through, with the inferred action of the tap/hold keys,
+    /// returns whether the log changed.
+    pub fn log_event(&mut self, event: &ControllerEvent) -> bool {
+        let events = self.tap_holds.resolve(event, Instant::now());
+        let mut changed = false;
+        for event in &events {
+            changed |= self.log_resolved_event(event);
+        }
+        changed
+    }
+
+    fn log_resolved_event(&mut self, event: &ControllerEvent) -> bool {
+        let Some(category) = LogCategory::of(event) else {
+            return false;
+        };
+        if !self.filter.contains(category) {
+            return false;
+        }
+        let Some(entry) = format_event(event) else {
+            return false;
+        };
+        if let Some(slot) = value_slot(event) {
+            let last_value = &mut self.last_values[slot];
+            if self.filter.changes_only && last_value.as_ref() == Some(&entry) {
+                return false;
             }
+            *last_value = Some(entry.clone());
+        }
+        self.log(entry);
+        true
+    }
+
+    /// Log the reason of the last reset, before the first event.
+    pub fn log_reset(&mut self, reason: ResetReason) {
+        let mut entry = LogEntry::new();
+        if entry.push_str("reset ").is_ok() && entry.push_str(reason.label()).is_ok() {
+            self.log(entry);
         }
-        self.flush_logs_to_display();
-        Ok(())
+    }
+
+    /// Count events which did not make it to the log.
+    pub fn drop_events(&mut self, count: u64) {
+        self.dropped = self.dropped.saturating_add(count as u32);
     }
 
     fn log(&mut self, entry: LogEntry) {
-        self.log_history.rotate_right(1);
-        self.log_history[0] = Some(entry);
+        if self.log_history.is_full() {
+            self.log_history.pop_back();
+        }
+        let record = LogRecord {
+            time: Instant::now(),
+            entry,
+        };
+        unwrap!(self.log_history.push_front(record).ok());
     }
 
-    fn flush_logs_to_display(&mut self) {
+    /// Log page, the most recent event first, skipping the `scroll` most recent lines.
+    ///
+    /// Each event shows the time elapsed since the previous one, or since boot for the oldest.
+    /// Events longer than a line continue on the following lines, without time.
+    /// The count of dropped events, if any, stays on the first line.
+    pub fn draw<D>(&self, scroll: usize, target: &mut D)
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        D::Error: defmt::Format,
+    {
         let log_style = MonoTextStyle::new(&LOG_STYLE, BinaryColor::Off);
-        self.display.clear_buffer();
-        for (index, log) in self.log_history.iter().enumerate() {
-            if let Some(text) = log {
-                let y = ((index + 1) * LOG_LINE_HEIGHT) as i32;
-                unwrap!(Text::new(text, Point { x: 2, y }, log_style).draw(&mut self.display));
+        let mut index = 0;
+        if self.dropped > 0 {
+            index += 1;
+            let mut buffer = itoa::Buffer::new();
+            let y = (index * LOG_LINE_HEIGHT) as i32;
+            unwrap!(Text::new("perdus", Point { x: 2, y }, log_style).draw(target));
+            let x = (2 + 7 * 4) as i32;
+            unwrap!(Text::new(buffer.format(self.dropped), Point { x, y }, log_style).draw(target));
+        }
+
+        let previous_times = self
+            .log_history
+            .iter()
+            .skip(1)
+            .map(|record| record.time)
+            .chain(core::iter::once(Instant::from_ticks(0)));
+        let lines =
+            self.log_history
+                .iter()
+                .zip(previous_times)
+                .flat_map(|(record, previous_time)| {
+                    wrap(&record.entry).enumerate().map(move |(line, text)| {
+                        let elapsed = (line == 0).then(|| record.time - previous_time);
+                        (elapsed, text)
+                    })
+                });
+        for (elapsed, text) in lines.skip(scroll).take(self.visible_lines()) {
+            index += 1;
+            let y = (index * LOG_LINE_HEIGHT) as i32;
+            if let Some(Ok(time)) = elapsed.map(format_elapsed) {
+                let x = (2 + LOG_TIME_COLUMNS * 4) as i32;
+                unwrap!(
+                    Text::with_alignment(&time, Point { x, y }, log_style, Alignment::Right)
+                        .draw(target)
+                );
             }
+            let x = (2 + (LOG_TIME_COLUMNS + 1) * 4) as i32;
+            unwrap!(Text::new(text, Point { x, y }, log_style).draw(target));
         }
-        self.display.flush_buffer();
     }
 }
 
-impl Controller for LogScreenController<'_> {
+/// Collect the controller events in `EVENT_LOG` and in the flash event log in the background.
+pub struct LogController {
+    pub sub: ControllerSub,
+    pub flash_log: FlashLog,
+}
+
+impl Controller for LogController {
     type Event = ControllerEvent;
 
     async fn process_event(&mut self, event: Self::Event) {
-        self.log_event(event).ok();
+        if EVENT_LOG.lock(|log| log.borrow_mut().log_event(&event)) {
+            EVENT_LOG_UPDATED.signal(());
+        }
+        // Every event, whatever the filter of the log page
+        self.flash_log.record(&event).await;
     }
 
     async fn next_message(&mut self) -> Self::Event {
-        self.sub.next_message_pure().await
+        loop {
+            let message = match select(self.sub.next_message(), self.flash_log.flush_due()).await {
+                Either::First(message) => message,
+                Either::Second(()) => {
+                    self.flash_log.flush().await;
+                    continue;
+                }
+            };
+            match message {
+                WaitResult::Message(event) => return event,
+                WaitResult::Lagged(count) => {
+                    EVENT_LOG.lock(|log| log.borrow_mut().drop_events(count));
+                    EVENT_LOG_UPDATED.signal(());
+                }
+            }
+        }
     }
 }
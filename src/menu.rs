@@ -0,0 +1,213 @@
+use embedded_graphics::{
+    mono_font::{iso_8859_1::FONT_5X8, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point, Primitive, Size},
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+    Drawable,
+};
+use heapless::String;
+use rmk::types::keycode::KeyCode;
+
+use crate::{
+    log_controller::{LogCategory, LogFilter},
+    nice_view::{Rotation, Theme},
+    settings::{IdleTimeout, Settings},
+    unwrap,
+};
+
+const MENU_LINE_HEIGHT: i32 = 10;
+const MENU_TOP: i32 = 16;
+
+/// Keys of the `peripherics` layer driving the menu.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MenuKey {
+    /// Open the menu, or close it and save the changes.
+    Toggle,
+    Up,
+    Down,
+    Select,
+}
+
+impl MenuKey {
+    pub fn from_key_code(key: KeyCode) -> Option<Self> {
+        match key {
+            KeyCode::User8 => Some(MenuKey::Toggle),
+            KeyCode::User9 => Some(MenuKey::Up),
+            KeyCode::User10 => Some(MenuKey::Down),
+            KeyCode::User11 => Some(MenuKey::Select),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum MenuItem {
+    Theme,
+    Rotation,
+    IdleTimeout,
+    Log(LogCategory),
+    LogRepeats,
+}
+
+const MENU_ITEMS: &[MenuItem] = &[
+    MenuItem::Theme,
+    MenuItem::Rotation,
+    MenuItem::IdleTimeout,
+    MenuItem::Log(LogCategory::Power),
+    MenuItem::Log(LogCategory::Ble),
+    MenuItem::Log(LogCategory::Split),
+    MenuItem::Log(LogCategory::Layer),
+    MenuItem::Log(LogCategory::Keys),
+    MenuItem::Log(LogCategory::Sleep),
+    MenuItem::LogRepeats,
+];
+
+impl MenuItem {
+    fn label(self) -> &'static str {
+        match self {
+            MenuItem::Theme => "Thème",
+            MenuItem::Rotation => "Rotation",
+            MenuItem::IdleTimeout => "Veille",
+            MenuItem::Log(LogCategory::Power) => "Log batt",
+            MenuItem::Log(LogCategory::Ble) => "Log BLE",
+            MenuItem::Log(LogCategory::Split) => "Log split",
+            MenuItem::Log(LogCategory::Layer) => "Log layer",
+            MenuItem::Log(LogCategory::Keys) => "Log touch",
+            MenuItem::Log(LogCategory::Sleep) => "Log dodo",
+            MenuItem::LogRepeats => "Log répét",
+        }
+    }
+
+    fn value(self, settings: &Settings, filter: &LogFilter) -> String<8> {
+        let yes_no = |value| if value { "oui" } else { "non" };
+        let text = match self {
+            MenuItem::Theme => match settings.theme {
+                Theme::Light => "clair",
+                Theme::Dark => "sombre",
+            },
+            MenuItem::Rotation => match settings.rotation {
+                Rotation::Normal => "0°",
+                Rotation::Flipped => "180°",
+            },
+            MenuItem::IdleTimeout => match settings.idle_timeout {
+                IdleTimeout::Never => "jamais",
+                IdleTimeout::Secs30 => "30s",
+                IdleTimeout::Min1 => "1min",
+                IdleTimeout::Min5 => "5min",
+            },
+            MenuItem::Log(category) => yes_no(filter.contains(category)),
+            MenuItem::LogRepeats => yes_no(!filter.changes_only),
+        };
+        // The longest value, "jamais", is 6 bytes
+        unwrap!(String::try_from(text).ok())
+    }
+}
+
+/// What the screen controller has to do after a menu key.
+pub enum MenuOutcome {
+    /// The menu changed, redraw it.
+    Redraw,
+    /// The menu has been closed, save the settings if they changed.
+    Closed,
+}
+
+/// Settings menu of the central screen.
+#[derive(Default)]
+pub struct Menu {
+    open: bool,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn handle(
+        &mut self,
+        key: MenuKey,
+        settings: &mut Settings,
+        filter: &mut LogFilter,
+    ) -> MenuOutcome {
+        if !self.open {
+            if key == MenuKey::Toggle {
+                self.open = true;
+                self.selected = 0;
+            }
+            return MenuOutcome::Redraw;
+        }
+        match key {
+            MenuKey::Toggle => {
+                self.open = false;
+                return MenuOutcome::Closed;
+            }
+            MenuKey::Up => {
+                self.selected = (self.selected + MENU_ITEMS.len() - 1) % MENU_ITEMS.len();
+            }
+            MenuKey::Down => {
+                self.selected = (self.selected + 1) % MENU_ITEMS.len();
+            }
+            MenuKey::Select => match MENU_ITEMS[self.selected] {
+                MenuItem::Theme => {
+                    settings.theme = match settings.theme {
+                        Theme::Light => Theme::Dark,
+                        Theme::Dark => Theme::Light,
+                    };
+                }
+                MenuItem::Rotation => {
+                    settings.rotation = match settings.rotation {
+                        Rotation::Normal => Rotation::Flipped,
+                        Rotation::Flipped => Rotation::Normal,
+                    };
+                }
+                MenuItem::IdleTimeout => {
+                    settings.idle_timeout = match settings.idle_timeout {
+                        IdleTimeout::Never => IdleTimeout::Secs30,
+                        IdleTimeout::Secs30 => IdleTimeout::Min1,
+                        IdleTimeout::Min1 => IdleTimeout::Min5,
+                        IdleTimeout::Min5 => IdleTimeout::Never,
+                    };
+                }
+                MenuItem::Log(category) => filter.toggle(category),
+                MenuItem::LogRepeats => filter.changes_only = !filter.changes_only,
+            },
+        }
+        MenuOutcome::Redraw
+    }
+
+    pub fn draw<D>(&self, settings: &Settings, filter: &LogFilter, target: &mut D)
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        D::Error: defmt::Format,
+    {
+        let width = target.bounding_box().size.width;
+        let style = MonoTextStyle::new(&FONT_5X8, BinaryColor::Off);
+        let selected_style = MonoTextStyle::new(&FONT_5X8, BinaryColor::On);
+
+        unwrap!(Text::new("MENU", Point::new(2, 8), style).draw(target));
+        for (index, item) in MENU_ITEMS.iter().enumerate() {
+            let top = MENU_TOP + index as i32 * MENU_LINE_HEIGHT;
+            let style = if index == self.selected {
+                unwrap!(Rectangle::new(
+                    Point::new(0, top),
+                    Size::new(width, MENU_LINE_HEIGHT as u32)
+                )
+                .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+                .draw(target));
+                selected_style
+            } else {
+                style
+            };
+            let baseline = top + MENU_LINE_HEIGHT - 3;
+            unwrap!(Text::new(item.label(), Point::new(2, baseline), style).draw(target));
+            unwrap!(Text::with_alignment(
+                &item.value(settings, filter),
+                Point::new(width as i32 - 2, baseline),
+                style,
+                Alignment::Right
+            )
+            .draw(target));
+        }
+    }
+}
@@ -10,6 +10,33 @@ use sharp_memory_display::*;
 
 use embedded_hal::digital::v2::OutputPin;
 
+use crate::{
+    config::{DISPLAY_ENABLED, DISPLAY_ROTATION},
+    unwrap,
+};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Theme {
+    /// Black drawings on the white background.
+    #[default]
+    Light,
+    Dark,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Rotation {
+    /// Connector at the top, as mounted on the Urchin.
+    Normal,
+    Flipped,
+}
+
+impl Default for Rotation {
+    /// `display.rotation` of `keyboard.toml`.
+    fn default() -> Self {
+        DISPLAY_ROTATION
+    }
+}
+
 struct NoPin;
 
 impl OutputPin for NoPin {
@@ -26,6 +53,8 @@ impl OutputPin for NoPin {
 
 pub struct NiceView<'a> {
     parent: MemoryDisplay<spim::Spim<'a>, Output<'a>, NoPin>,
+    theme: Theme,
+    rotation: Rotation,
 }
 
 impl<'a> NiceView<'a> {
@@ -33,22 +62,45 @@ impl<'a> NiceView<'a> {
         let disp = NoPin;
         Self {
             parent: MemoryDisplay::new(spi, cs, disp),
+            theme: Theme::default(),
+            rotation: Rotation::default(),
         }
     }
 
+    /// Invert all the drawn pixels with the dark theme.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Turn the drawings upside down when the keyboard half is mounted the other way.
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
     /// Clear the screen and the internal framebuffer.
     pub fn clear(&mut self) {
-        self.parent.clear()
+        if DISPLAY_ENABLED {
+            self.parent.clear()
+        } else {
+            self.parent.clear_buffer()
+        }
     }
 
     /// Clear just the internal framebuffer, without writing changes to the display.
     pub fn clear_buffer(&mut self) {
-        self.parent.clear_buffer()
+        self.parent.clear_buffer();
+        if self.theme == Theme::Dark {
+            unwrap!(DrawTarget::clear(&mut self.parent, BinaryColor::Off));
+        }
     }
 
     /// Draw all lines of the buffer to the screen which have changed since last calling this function.
+    ///
+    /// With `display.enabled = false`, nothing is ever written and the screen stays blank.
     pub fn flush_buffer(&mut self) {
-        self.parent.flush_buffer()
+        if DISPLAY_ENABLED {
+            self.parent.flush_buffer()
+        }
     }
 }
 
@@ -60,13 +112,22 @@ impl<'a> DrawTarget for NiceView<'a> {
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
-        let parent_width = self.parent.bounding_box().size.width as i32;
+        let parent_size = self.parent.bounding_box().size;
+        let parent_width = parent_size.width as i32;
+        let parent_height = parent_size.height as i32;
+        let theme = self.theme;
+        let rotation = self.rotation;
 
-        self.parent.draw_iter(
-            pixels
-                .into_iter()
-                .map(|Pixel(p, c)| Pixel(Point::new(parent_width - p.y, p.x), c)),
-        )
+        self.parent.draw_iter(pixels.into_iter().map(|Pixel(p, c)| {
+            let c = match theme {
+                Theme::Light => c,
+                Theme::Dark => c.invert(),
+            };
+            match rotation {
+                Rotation::Normal => Pixel(Point::new(parent_width - p.y, p.x), c),
+                Rotation::Flipped => Pixel(Point::new(p.y, parent_height - 1 - p.x), c),
+            }
+        }))
     }
 }
 
@@ -0,0 +1,117 @@
+use embassy_time::{Duration, Instant};
+use embedded_graphics::{
+    mono_font::{iso_8859_1::FONT_6X10, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point, Primitive, Size},
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+    Drawable,
+};
+use heapless::{Deque, String};
+
+use crate::unwrap;
+
+/// How long a notification stays on screen.
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(3);
+/// Notifications waiting to be shown, including the one on screen.
+const NOTIFICATION_QUEUE: usize = 4;
+/// Bytes per line, the screen fits 11 characters of `FONT_6X10`.
+const NOTIFICATION_COLUMNS: usize = 24;
+
+const NOTIFICATION_TOP: i32 = 96;
+const NOTIFICATION_HEIGHT: u32 = 28;
+
+pub type NotificationLine = String<NOTIFICATION_COLUMNS>;
+
+pub struct Notification {
+    pub title: NotificationLine,
+    pub detail: NotificationLine,
+}
+
+impl Notification {
+    /// Build a notification, cutting the lines that do not fit.
+    pub fn new(title: &str, detail: &str) -> Self {
+        Self {
+            title: truncated(title),
+            detail: truncated(detail),
+        }
+    }
+}
+
+fn truncated(text: &str) -> NotificationLine {
+    let mut line = NotificationLine::new();
+    for c in text.chars() {
+        if line.push(c).is_err() {
+            break;
+        }
+    }
+    line
+}
+
+/// Short messages shown over the status screen, one at a time.
+#[derive(Default)]
+pub struct NotificationQueue {
+    queue: Deque<Notification, NOTIFICATION_QUEUE>,
+    shown_until: Option<Instant>,
+}
+
+impl NotificationQueue {
+    /// Queue a notification, dropping the oldest one if the queue is full.
+    pub fn push(&mut self, notification: Notification) {
+        if self.queue.is_full() {
+            self.queue.pop_front();
+            self.shown_until = None;
+        }
+        unwrap!(self.queue.push_back(notification).ok());
+        if self.shown_until.is_none() {
+            self.shown_until = Some(Instant::now() + NOTIFICATION_DURATION);
+        }
+    }
+
+    /// Time at which the current notification must be removed.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.shown_until
+    }
+
+    /// Remove the current notification and start showing the next one.
+    pub fn expire(&mut self) {
+        self.queue.pop_front();
+        self.shown_until = if self.queue.is_empty() {
+            None
+        } else {
+            Some(Instant::now() + NOTIFICATION_DURATION)
+        };
+    }
+
+    /// Draw the current notification, if any, over what is already drawn.
+    pub fn draw<D>(&self, target: &mut D)
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        D::Error: defmt::Format,
+    {
+        let Some(notification) = self.queue.front() else {
+            return;
+        };
+        let width = target.bounding_box().size.width;
+        unwrap!(Rectangle::new(
+            Point::new(0, NOTIFICATION_TOP),
+            Size::new(width, NOTIFICATION_HEIGHT)
+        )
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(target));
+
+        let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        unwrap!(Text::new(
+            &notification.title,
+            Point::new(2, NOTIFICATION_TOP + 11),
+            style
+        )
+        .draw(target));
+        unwrap!(Text::new(
+            &notification.detail,
+            Point::new(2, NOTIFICATION_TOP + 23),
+            style
+        )
+        .draw(target));
+    }
+}
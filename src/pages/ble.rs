@@ -0,0 +1,54 @@
+use embedded_graphics::{
+    mono_font::{ascii::FONT_7X13_BOLD, iso_8859_1::FONT_6X10, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point},
+    text::Text,
+    Drawable,
+};
+
+use super::{MyBleState, ScreenState};
+use crate::unwrap;
+
+/// Connection details page.
+pub fn draw<D>(state: &ScreenState, target: &mut D)
+where
+    D: DrawTarget<Color = BinaryColor>,
+    D::Error: defmt::Format,
+{
+    let title_style = MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::Off);
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::Off);
+
+    unwrap!(Text::new("BLE", Point { x: 2, y: 14 }, title_style).draw(target));
+
+    let lines = [
+        "profil",
+        match state.ble_profile {
+            0 => " 1",
+            1 => " 2",
+            2 => " 3",
+            _ => " ?",
+        },
+        "état",
+        match state.ble_state {
+            MyBleState::Advertising => " annonce",
+            MyBleState::Connected => " connecté",
+            MyBleState::None => " aucun",
+        },
+        "sortie",
+        if state.connection_type == 0 {
+            " USB"
+        } else {
+            " BLE"
+        },
+        "droite",
+        if state.peripheral_connected {
+            " connectée"
+        } else {
+            " absente"
+        },
+    ];
+    for (index, line) in lines.iter().enumerate() {
+        let y = 32 + index as i32 * 11;
+        unwrap!(Text::new(line, Point { x: 2, y }, style).draw(target));
+    }
+}
@@ -0,0 +1,112 @@
+//! Pages of the central screen, each one with its own renderer.
+
+use embedded_graphics::{
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point, Primitive, Size},
+    primitives::{PrimitiveStyle, Rectangle},
+    Drawable,
+};
+
+use crate::{diagnostics::draw_battery_diagnostics, log_controller::EVENT_LOG, unwrap};
+
+pub use stats::KeyStats;
+
+mod ble;
+mod stats;
+mod status;
+
+const PAGE_INDICATOR_SIZE: u32 = 4;
+const PAGE_INDICATOR_SPACING: i32 = 7;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Page {
+    Status,
+    Battery,
+    Stats,
+    Ble,
+    Log,
+}
+
+const PAGES: [Page; 5] = [
+    Page::Status,
+    Page::Battery,
+    Page::Stats,
+    Page::Ble,
+    Page::Log,
+];
+
+impl Page {
+    /// Page shown at boot and after a while without changing pages, the log with `log-screen`.
+    pub const HOME: Page = if cfg!(feature = "log-screen") {
+        Page::Log
+    } else {
+        Page::Status
+    };
+
+    fn index(self) -> usize {
+        unwrap!(PAGES.iter().position(|&page| page == self))
+    }
+
+    pub fn next(self) -> Self {
+        PAGES[(self.index() + 1) % PAGES.len()]
+    }
+}
+
+#[derive(Default, PartialEq)]
+pub enum MyBleState {
+    Advertising,
+    Connected,
+    #[default]
+    None,
+}
+
+#[derive(Default)]
+pub struct ScreenState {
+    pub layer: u8,
+    pub ble_profile: u8,
+    pub ble_state: MyBleState,
+    pub connection_type: u8,
+    pub battery_percent: u8,
+    pub battery_millivolts: u16,
+    pub charging_state: bool,
+    pub peripheral_connected: bool,
+    pub peripheral_was_connected: bool,
+    pub key_stats: KeyStats,
+    /// Events of the log page hidden above the screen.
+    pub log_scroll: usize,
+}
+
+/// Draw a page and the indicator of its position at the bottom of the screen.
+pub fn draw_page<D>(page: Page, state: &mut ScreenState, target: &mut D)
+where
+    D: DrawTarget<Color = BinaryColor>,
+    D::Error: defmt::Format,
+{
+    match page {
+        Page::Status => status::draw(state, target),
+        Page::Battery => {
+            draw_battery_diagnostics(target, state.battery_percent, state.battery_millivolts)
+        }
+        Page::Stats => stats::draw(&mut state.key_stats, target),
+        Page::Ble => ble::draw(state, target),
+        Page::Log => EVENT_LOG.lock(|log| log.borrow().draw(state.log_scroll, target)),
+    }
+
+    let bounding_box = target.bounding_box();
+    let width = PAGES.len() as i32 * PAGE_INDICATOR_SPACING;
+    let left = (bounding_box.size.width as i32 - width) / 2;
+    let top = bounding_box.size.height as i32 - PAGE_INDICATOR_SIZE as i32 - 1;
+    for (index, &other) in PAGES.iter().enumerate() {
+        let style = if other == page {
+            PrimitiveStyle::with_fill(BinaryColor::Off)
+        } else {
+            PrimitiveStyle::with_stroke(BinaryColor::Off, 1)
+        };
+        unwrap!(Rectangle::new(
+            Point::new(left + index as i32 * PAGE_INDICATOR_SPACING, top),
+            Size::new(PAGE_INDICATOR_SIZE, PAGE_INDICATOR_SIZE),
+        )
+        .into_styled(style)
+        .draw(target));
+    }
+}
@@ -0,0 +1,87 @@
+use embassy_time::{Duration, Instant};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_7X13_BOLD, FONT_9X15},
+        iso_8859_1::FONT_6X10,
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point},
+    text::Text,
+    Drawable,
+};
+
+use crate::unwrap;
+
+/// Key presses are counted in buckets, the typing speed is computed over the last minute.
+const STATS_BUCKET: Duration = Duration::from_secs(5);
+const STATS_BUCKETS: usize = 12;
+/// Key presses per word, as usual for the words per minute.
+const PRESSES_PER_WORD: u32 = 5;
+
+#[derive(Default)]
+pub struct KeyStats {
+    total: u32,
+    buckets: [u16; STATS_BUCKETS],
+    current_bucket: u64,
+}
+
+impl KeyStats {
+    pub fn record_press(&mut self, now: Instant) {
+        self.advance(now);
+        let bucket = &mut self.buckets[self.current_bucket as usize % STATS_BUCKETS];
+        *bucket = bucket.saturating_add(1);
+        self.total = self.total.saturating_add(1);
+    }
+
+    /// Forget the buckets older than a minute.
+    fn advance(&mut self, now: Instant) {
+        let bucket = now.as_ticks() / STATS_BUCKET.as_ticks();
+        if bucket - self.current_bucket >= STATS_BUCKETS as u64 {
+            self.buckets = [0; STATS_BUCKETS];
+        } else {
+            for index in self.current_bucket + 1..=bucket {
+                self.buckets[index as usize % STATS_BUCKETS] = 0;
+            }
+        }
+        self.current_bucket = bucket;
+    }
+
+    /// Words per minute over the last minute.
+    pub fn words_per_minute(&mut self, now: Instant) -> u32 {
+        self.advance(now);
+        let presses: u32 = self.buckets.iter().map(|&b| b as u32).sum();
+        presses / PRESSES_PER_WORD
+    }
+}
+
+/// Typing statistics page.
+pub fn draw<D>(stats: &mut KeyStats, target: &mut D)
+where
+    D: DrawTarget<Color = BinaryColor>,
+    D::Error: defmt::Format,
+{
+    let title_style = MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::Off);
+    let label_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::Off);
+    let value_style = MonoTextStyle::new(&FONT_9X15, BinaryColor::Off);
+
+    unwrap!(Text::new("FRAPPE", Point { x: 2, y: 14 }, title_style).draw(target));
+
+    let mut buffer = itoa::Buffer::new();
+    unwrap!(Text::new("touches", Point { x: 2, y: 34 }, label_style).draw(target));
+    unwrap!(Text::new(
+        buffer.format(stats.total),
+        Point { x: 2, y: 50 },
+        value_style
+    )
+    .draw(target));
+
+    let words_per_minute = stats.words_per_minute(Instant::now());
+    unwrap!(Text::new("mots/min", Point { x: 2, y: 70 }, label_style).draw(target));
+    unwrap!(Text::new(
+        buffer.format(words_per_minute),
+        Point { x: 2, y: 86 },
+        value_style
+    )
+    .draw(target));
+}
@@ -0,0 +1,177 @@
+use embedded_graphics::{
+    image::{Image, ImageRaw},
+    mono_font::{
+        ascii::{FONT_10X20, FONT_7X13_BOLD, FONT_9X15},
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point},
+    text::Text,
+    Drawable,
+};
+
+use super::{MyBleState, ScreenState};
+use crate::unwrap;
+
+#[rustfmt::skip]
+const BLUETOOTH_NONE_DATA: &[u8] = &[
+    0b11111111, 0b01111111, 0b11_000000,
+    0b11111111, 0b00111111, 0b11_000000,
+    0b11111111, 0b00011111, 0b11_000000,
+    0b11111111, 0b00001111, 0b11_000000,
+    0b11111111, 0b00000111, 0b11_000000,
+    0b11111111, 0b00100011, 0b11_000000,
+    0b00111111, 0b00110001, 0b11_000000,
+    0b00011111, 0b00111000, 0b11_000000,
+    0b10001111, 0b00111100, 0b01_000000,
+    0b11000111, 0b00111000, 0b11_000000,
+    0b11100011, 0b10110001, 0b11_000000,
+    0b11110001, 0b11100011, 0b11_000000,
+    0b11111000, 0b11100111, 0b11_000000,
+    0b11111100, 0b01111111, 0b11_000000,
+    0b11111110, 0b00111111, 0b11_000000,
+    0b11111110, 0b00011111, 0b11_000000,
+    0b11111100, 0b00001111, 0b11_000000,
+    0b11111000, 0b00000111, 0b11_000000,
+    0b11110001, 0b00100011, 0b11_000000,
+    0b11100011, 0b00110001, 0b11_000000,
+    0b11000111, 0b00111000, 0b11_000000,
+    0b11001111, 0b00111100, 0b01_000000,
+    0b11111111, 0b00111000, 0b00_000000,
+    0b11111111, 0b00110001, 0b11_000000,
+    0b11111111, 0b00100011, 0b11_000000,
+    0b11111111, 0b00000111, 0b11_000000,
+    0b11111111, 0b00001111, 0b11_000000,
+    0b11111111, 0b00011111, 0b11_000000,
+    0b11111111, 0b00111111, 0b11_000000,
+    0b11111111, 0b01111111, 0b11_000000,
+];
+
+#[rustfmt::skip]
+const BLUETOOTH_ADVERTISING_DATA: &[u8] = &[
+    0b11111111, 0b01111111, 0b11_000000,
+    0b11111111, 0b00111111, 0b11_000000,
+    0b11111111, 0b00011111, 0b11_000000,
+    0b11111111, 0b00001111, 0b11_000000,
+    0b11111111, 0b00000111, 0b11_000000,
+    0b11111111, 0b00100011, 0b11_000000,
+    0b11111111, 0b00110001, 0b11_000000,
+    0b11111111, 0b00111000, 0b11_000000,
+    0b11001111, 0b00111100, 0b11_000000,
+    0b11000111, 0b00111000, 0b11_000000,
+    0b11100011, 0b00110001, 0b11_000000,
+    0b11110001, 0b00100011, 0b11_000000,
+    0b11111000, 0b00000111, 0b11_000000,
+    0b11111100, 0b00001111, 0b11_000000,
+    0b11111110, 0b00011111, 0b11_000000,
+    0b11111110, 0b00011111, 0b11_000000,
+    0b11111100, 0b00001111, 0b11_000000,
+    0b11111000, 0b00000111, 0b11_000000,
+    0b11110001, 0b00100011, 0b11_000000,
+    0b11100011, 0b00110001, 0b11_000000,
+    0b11000111, 0b00111000, 0b11_000000,
+    0b11001111, 0b00111100, 0b11_000000,
+    0b11111111, 0b00111000, 0b11_000000,
+    0b11111111, 0b00110001, 0b11_000000,
+    0b11111111, 0b00100011, 0b11_000000,
+    0b11111111, 0b00000111, 0b11_000000,
+    0b11111111, 0b00001111, 0b11_000000,
+    0b11111111, 0b00011111, 0b11_000000,
+    0b11111111, 0b00111111, 0b11_000000,
+    0b11111111, 0b01111111, 0b11_000000,
+];
+
+#[rustfmt::skip]
+const BLUETOOTH_CONNECTED_DATA: &[u8] = &[
+    0b11111111, 0b01111111, 0b11_000000,
+    0b11111111, 0b00111111, 0b11_000000,
+    0b11111111, 0b00011111, 0b11_000000,
+    0b11111111, 0b00001111, 0b11_000000,
+    0b11111111, 0b00000111, 0b11_000000,
+    0b11111111, 0b00100011, 0b11_000000,
+    0b11111111, 0b00110001, 0b11_000000,
+    0b11111111, 0b00111000, 0b11_000000,
+    0b11001111, 0b00111100, 0b11_000000,
+    0b11000111, 0b00111000, 0b11_000000,
+    0b11100011, 0b00110001, 0b11_000000,
+    0b11110001, 0b00100011, 0b11_000000,
+    0b10011000, 0b00000110, 0b01_000000,
+    0b00111100, 0b00001111, 0b00_000000,
+    0b01100110, 0b00011001, 0b10_000000,
+    0b01100110, 0b00011001, 0b10_000000,
+    0b00111100, 0b00001111, 0b00_000000,
+    0b10011000, 0b00000110, 0b01_000000,
+    0b11110001, 0b00100011, 0b11_000000,
+    0b11100011, 0b00110001, 0b11_000000,
+    0b11000111, 0b00111000, 0b11_000000,
+    0b11001111, 0b00111100, 0b11_000000,
+    0b11111111, 0b00111000, 0b11_000000,
+    0b11111111, 0b00110001, 0b11_000000,
+    0b11111111, 0b00100011, 0b11_000000,
+    0b11111111, 0b00000111, 0b11_000000,
+    0b11111111, 0b00001111, 0b11_000000,
+    0b11111111, 0b00011111, 0b11_000000,
+    0b11111111, 0b00111111, 0b11_000000,
+    0b11111111, 0b01111111, 0b11_000000,
+];
+
+#[rustfmt::skip]
+const USB_DATA: &[u8] = &[
+    0b11111111, 0b11111001, 0b11111111, 0b111_00000,
+    0b11111111, 0b11000000, 0b11111111, 0b111_00000,
+    0b11111111, 0b10011001, 0b11111111, 0b111_00000,
+    0b10001111, 0b00111111, 0b11111111, 0b011_00000,
+    0b00000110, 0b01111111, 0b11111111, 0b001_00000,
+    0b00000000, 0b00000000, 0b00000000, 0b000_00000,
+    0b00000111, 0b11110011, 0b11111111, 0b001_00000,
+    0b10001111, 0b11111001, 0b11111111, 0b011_00000,
+    0b11111111, 0b11111100, 0b11111111, 0b111_00000,
+    0b11111111, 0b11111110, 0b01100011, 0b111_00000,
+    0b11111111, 0b11111111, 0b00000011, 0b111_00000,
+    0b11111111, 0b11111111, 0b11000011, 0b111_00000,
+];
+
+/// Main page: connection, layer and battery.
+pub fn draw<D>(state: &ScreenState, target: &mut D)
+where
+    D: DrawTarget<Color = BinaryColor>,
+    D::Error: defmt::Format,
+{
+    if state.connection_type == 0 {
+        let raw_image = ImageRaw::<BinaryColor>::new(USB_DATA, 27);
+        unwrap!(Image::new(&raw_image, Point { x: 2, y: 10 }).draw(target));
+    } else {
+        let profile = match state.ble_profile {
+            0 => "1",
+            1 => "2",
+            2 => "3",
+            _ => "?",
+        };
+        let profile_style = MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::Off);
+        let raw_image = ImageRaw::<BinaryColor>::new(
+            match state.ble_state {
+                MyBleState::Advertising => BLUETOOTH_ADVERTISING_DATA,
+                MyBleState::Connected => BLUETOOTH_CONNECTED_DATA,
+                MyBleState::None => BLUETOOTH_NONE_DATA,
+            },
+            18,
+        );
+        unwrap!(Image::new(&raw_image, Point { x: 2, y: 2 }).draw(target));
+        unwrap!(Text::new(profile, Point { x: 22, y: 27 }, profile_style).draw(target));
+    }
+    let layer = match state.layer {
+        0 => "TEXTE",
+        1 => "NAV",
+        2 => "PROG",
+        3 => "PERI",
+        _ => "",
+    };
+    let profile_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+    unwrap!(Text::new(layer, Point { x: 6, y: 70 }, profile_style).draw(target));
+
+    let battery_style = MonoTextStyle::new(&FONT_9X15, BinaryColor::Off);
+
+    let mut battery_buffer = itoa::Buffer::new();
+    let battery_repr = battery_buffer.format(state.battery_percent);
+    unwrap!(Text::new(battery_repr, Point { x: 32, y: 32 }, battery_style).draw(target));
+}
@@ -1,56 +1,122 @@
 #![no_main]
 #![no_std]
 
-use defmt::unwrap;
-use embassy_nrf::{
-    gpio::{Level, Output, OutputDrive},
-    peripherals, spim,
-};
-use rmk::{
-    channel::{ControllerSub, CONTROLLER_CHANNEL},
-    controller::Controller,
-    event::ControllerEvent,
-};
-use sharp_memory_display::*;
+#[cfg(not(feature = "display"))]
+use embassy_nrf::gpio::{Output, OutputDrive};
+use embassy_nrf::saadc::{self, Input as _};
+use embassy_time::Instant;
+use rmk::channel::CONTROLLER_CHANNEL;
 
 use rmk::macros::rmk_peripheral;
 
+#[cfg_attr(not(feature = "display"), allow(dead_code))]
+mod battery;
+mod battery_curve;
+mod config;
+#[cfg_attr(not(feature = "display"), allow(dead_code))]
+mod crash;
+#[cfg(feature = "display")]
+mod diagnostics;
+#[cfg(feature = "display")]
+mod display;
+mod flash;
+#[cfg(feature = "display")]
 mod nice_view;
+#[cfg(feature = "display")]
+mod peripheral_screen;
+#[cfg(not(feature = "display"))]
+mod status_led;
+mod watchdog;
 
-use crate::nice_view::NiceView;
+use crate::{
+    battery::BatteryMonitor,
+    watchdog::{start_watchdog, watchdog_handle, RmkEvents, Supervised},
+};
+#[cfg(feature = "display")]
+use crate::{display::init_display, peripheral_screen::DiagnosticsScreenController};
+#[cfg(not(feature = "display"))]
+use crate::{
+    crash::take_crash_report,
+    status_led::{BlinkCode, StatusLed, LED_OFF},
+    watchdog::reset_reason,
+};
 
-struct EmptyScreenController {
-    sub: ControllerSub,
-}
+/// What shows the state of the half: the screen, or the blink codes of the LED without one.
+#[cfg(feature = "display")]
+type StatusController<'a> = Supervised<DiagnosticsScreenController<'a>>;
+#[cfg(not(feature = "display"))]
+type StatusController<'a> = Supervised<StatusLed<'a>>;
 
-impl Controller for EmptyScreenController {
-    type Event = ControllerEvent;
+/// Tasks with a handle of the watchdog: the status controller, the battery monitor and the
+/// events of RMK.
+const SUPERVISED_TASKS: usize = 3;
 
-    async fn next_message(&mut self) -> Self::Event {
-        self.sub.next_message_pure().await
-    }
+/// The peripheral half, `$id` being its index in `[[split.peripheral]]` of `keyboard.toml`.
+macro_rules! keyboard_peripheral {
+    ($id:literal) => {
+        /// Label of the half on the boot splash.
+        #[cfg(feature = "display")]
+        const HALF_LABEL: &str = concat!("periph ", $id);
 
-    async fn process_event(&mut self, _event: Self::Event) {}
-}
+        #[rmk_peripheral(id = $id)]
+        mod keyboard_peripheral {
+            #[controller(event)]
+            fn status_controller() -> StatusController {
+                // The first controller, the others take their handle after
+                start_watchdog::<SUPERVISED_TASKS>(p.WDT);
+                #[cfg(feature = "display")]
+                let status_controller = {
+                    let (spi, sck, mosi, cs) = config::display_peripherals!(p);
+                    DiagnosticsScreenController::new(init_display(spi, sck, mosi, cs))
+                };
+                #[cfg(not(feature = "display"))]
+                let status_controller = StatusLed {
+                    sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+                    led: Output::new(
+                        config::status_led_pin!(p),
+                        LED_OFF,
+                        OutputDrive::Standard,
+                    ),
+                    pending: Some(BlinkCode::at_boot(
+                        reset_reason(),
+                        take_crash_report().as_ref(),
+                    )),
+                    low_battery: false,
+                };
+                Supervised::new(status_controller)
+            }
+
+            #[controller(poll)]
+            fn battery_monitor() -> BatteryMonitor {
+                bind_interrupts!(struct BatteryIrqs {
+                    SAADC => saadc::InterruptHandler;
+                });
+                let channel_config =
+                    saadc::ChannelConfig::single_ended(config::battery_adc_input!(p));
+                let saadc = saadc::Saadc::new(
+                    p.SAADC,
+                    BatteryIrqs,
+                    saadc::Config::default(),
+                    [channel_config],
+                );
 
-#[rmk_peripheral(id = 0)]
-mod keyboard_peripheral {
-    #[controller(event)]
-    fn screen_controller() -> EmptyScreenController {
-        bind_interrupts!(struct Irqs {
-            SPIM3 => spim::InterruptHandler<peripherals::SPI3>;
-        });
-        let mut config = spim::Config::default();
-        config.mode = MODE;
-        let spi = spim::Spim::new_txonly(p.SPI3, Irqs, p.P0_20, p.P0_17, config);
-        let cs = Output::new(p.P0_06, Level::High, OutputDrive::Standard);
-        let mut display = NiceView::new(spi, cs);
-        display.clear();
-        display.clear_buffer();
-        display.flush_buffer();
-
-        EmptyScreenController {
-            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+                BatteryMonitor {
+                    publisher: unwrap!(CONTROLLER_CHANNEL.publisher()),
+                    saadc,
+                    divider_switch: config::battery_divider_switch!(p),
+                    watchdog: watchdog_handle(),
+                    next_measurement: Instant::now(),
+                }
+            }
+
+            #[controller(event)]
+            fn rmk_events() -> Supervised<RmkEvents> {
+                Supervised::new(RmkEvents {
+                    sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+                })
+            }
         }
-    }
+    };
 }
+
+keyboard_peripheral!(0);
@@ -0,0 +1,112 @@
+//! Screen of the peripheral half, built with the `display` feature.
+
+use embassy_time::{with_deadline, Duration, Instant};
+use rmk::{
+    channel::{ControllerSub, CONTROLLER_CHANNEL},
+    controller::Controller,
+    event::ControllerEvent,
+};
+
+use crate::{
+    battery::battery_millivolts,
+    crash::{save_crash_report, take_crash_report, CrashReport},
+    diagnostics::{draw_battery_diagnostics, draw_boot_splash, draw_crash_report, SPLASH_DURATION},
+    nice_view::NiceView,
+    unwrap,
+    watchdog::reset_reason,
+    HALF_LABEL,
+};
+
+/// How long the crash which caused the last reset stays on the screen, after the splash.
+const CRASH_REPORT_DURATION: Duration = Duration::from_secs(30);
+
+/// Shown at boot instead of the battery diagnostics.
+enum BootScreen {
+    Splash,
+    Crash(CrashReport),
+}
+
+/// Screen of the peripheral: battery diagnostics, after the boot screens.
+pub struct DiagnosticsScreenController<'a> {
+    sub: ControllerSub,
+    display: NiceView<'a>,
+    battery_percent: u8,
+    battery_millivolts: u16,
+    /// Screen shown at boot, until the given time.
+    boot_screen: Option<(BootScreen, Instant)>,
+    /// Crash which caused the last reset, shown after the splash.
+    crash_report: Option<CrashReport>,
+}
+
+impl<'a> DiagnosticsScreenController<'a> {
+    /// Start with the boot splash, then the crash report if the last reset was a crash.
+    pub fn new(display: NiceView<'a>) -> Self {
+        let mut screen_controller = DiagnosticsScreenController {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            display,
+            battery_percent: 0,
+            battery_millivolts: 0,
+            boot_screen: Some((BootScreen::Splash, Instant::now() + SPLASH_DURATION)),
+            crash_report: take_crash_report(),
+        };
+        screen_controller.flush_state_to_the_display();
+        screen_controller
+    }
+
+    fn flush_state_to_the_display(&mut self) {
+        self.display.clear_buffer();
+        match &self.boot_screen {
+            Some((BootScreen::Crash(report), _)) => draw_crash_report(&mut self.display, report),
+            Some((BootScreen::Splash, _)) => {
+                draw_boot_splash(&mut self.display, HALF_LABEL, reset_reason())
+            }
+            None => draw_battery_diagnostics(
+                &mut self.display,
+                self.battery_percent,
+                self.battery_millivolts,
+            ),
+        }
+        self.display.flush_buffer();
+    }
+}
+
+impl Controller for DiagnosticsScreenController<'_> {
+    /// `None` when the boot screen has been shown long enough.
+    type Event = Option<ControllerEvent>;
+
+    async fn next_message(&mut self) -> Self::Event {
+        save_crash_report().await;
+        let Some((_, shown_until)) = self.boot_screen else {
+            return Some(self.sub.next_message_pure().await);
+        };
+        with_deadline(shown_until, self.sub.next_message_pure())
+            .await
+            .ok()
+    }
+
+    async fn process_event(&mut self, event: Self::Event) {
+        let Some(event) = event else {
+            self.boot_screen = self.crash_report.take().map(|report| {
+                (
+                    BootScreen::Crash(report),
+                    Instant::now() + CRASH_REPORT_DURATION,
+                )
+            });
+            self.flush_state_to_the_display();
+            return;
+        };
+        let ControllerEvent::Battery(battery_percent) = event else {
+            return;
+        };
+        let battery_millivolts = battery_millivolts();
+        if battery_percent == self.battery_percent && battery_millivolts == self.battery_millivolts
+        {
+            return;
+        }
+        self.battery_percent = battery_percent;
+        self.battery_millivolts = battery_millivolts;
+        if self.boot_screen.is_none() {
+            self.flush_state_to_the_display();
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use embassy_time::Duration;
+
+use nrf_mpsl::Flash;
+
+use crate::{
+    config::{DISPLAY_ROTATION, SETTINGS_PAGES},
+    flash::shared_flash,
+    nice_view::{Rotation, Theme},
+    settings_store::{Key, SettingValue, SettingsStore},
+};
+
+// Keys of the settings store, a key is never reused for another setting: 4 and 5 held morse
+// timeouts, which RMK does not let the firmware change
+pub const THEME: Key<Theme> = Key {
+    id: 1,
+    default: Theme::Light,
+};
+/// Defaults to `display.rotation` of `keyboard.toml`.
+pub const ROTATION: Key<Rotation> = Key {
+    id: 2,
+    default: DISPLAY_ROTATION,
+};
+pub const IDLE_TIMEOUT: Key<IdleTimeout> = Key {
+    id: 3,
+    default: IdleTimeout::Min1,
+};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum IdleTimeout {
+    Never,
+    Secs30,
+    #[default]
+    Min1,
+    Min5,
+}
+
+impl IdleTimeout {
+    /// Inactivity after which the screen is turned off.
+    pub fn duration(self) -> Option<Duration> {
+        match self {
+            IdleTimeout::Never => None,
+            IdleTimeout::Secs30 => Some(Duration::from_secs(30)),
+            IdleTimeout::Min1 => Some(Duration::from_secs(60)),
+            IdleTimeout::Min5 => Some(Duration::from_secs(300)),
+        }
+    }
+}
+
+/// Preferences changed from the on-device menu, kept in the settings store.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub theme: Theme,
+    pub rotation: Rotation,
+    pub idle_timeout: IdleTimeout,
+}
+
+/// A setting of a single byte, its `u8` value.
+macro_rules! byte_setting {
+    ($type:ty, $($value:literal => $variant:expr),+ $(,)?) => {
+        impl SettingValue for $type {
+            const SIZE: usize = 1;
+
+            fn encode(self, bytes: &mut [u8]) {
+                bytes[0] = self as u8;
+            }
+
+            fn decode(bytes: &[u8]) -> Option<Self> {
+                match bytes[0] {
+                    $($value => Some($variant),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+byte_setting!(Theme, 0 => Theme::Light, 1 => Theme::Dark);
+byte_setting!(Rotation, 0 => Rotation::Normal, 1 => Rotation::Flipped);
+byte_setting!(
+    IdleTimeout,
+    0 => IdleTimeout::Never,
+    1 => IdleTimeout::Secs30,
+    2 => IdleTimeout::Min1,
+    3 => IdleTimeout::Min5,
+);
+
+impl Settings {
+    /// Settings before the store is read.
+    pub const DEFAULT: Settings = Settings {
+        theme: THEME.default,
+        rotation: ROTATION.default,
+        idle_timeout: IDLE_TIMEOUT.default,
+    };
+
+    /// Read the settings from the store, the defaults for the ones never changed.
+    pub async fn load() -> Self {
+        let Some(mut store) = open_store().await else {
+            return Self::DEFAULT;
+        };
+        Self {
+            theme: store.get(&THEME).await,
+            rotation: store.get(&ROTATION).await,
+            idle_timeout: store.get(&IDLE_TIMEOUT).await,
+        }
+    }
+
+    /// Write the changed settings to the store.
+    ///
+    /// This is only done when the menu is closed with changes, the store erases its page from
+    /// time to time.
+    pub async fn save(&self) {
+        let Some(mut store) = open_store().await else {
+            return;
+        };
+        let saved = async {
+            store.set(&THEME, self.theme).await?;
+            store.set(&ROTATION, self.rotation).await?;
+            store.set(&IDLE_TIMEOUT, self.idle_timeout).await
+        };
+        if let Err(e) = saved.await {
+            defmt::error!("Failed to write the settings: {}", e);
+        }
+    }
+}
+
+async fn open_store() -> Option<SettingsStore<Flash>> {
+    match SettingsStore::open(shared_flash(), SETTINGS_PAGES).await {
+        Ok(store) => Some(store),
+        Err(e) => {
+            defmt::error!("Failed to open the settings: {}", e);
+            None
+        }
+    }
+}
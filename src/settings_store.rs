@@ -0,0 +1,492 @@
+//! Typed and versioned key-value store of the firmware settings, in the two settings flash pages
+//! below RMK's storage.
+//!
+//! A page starts with a header, then records appended one after the other: a word with the key,
+//! the length and a checksum of the value, then the value padded to a word. The last valid record
+//! of a key wins, a key without one reads as its default. When the page is full, the last record of
+//! each key is written to the other page, which takes over, and only then is the full page erased.
+//! The header is written last and holds a sequence number: after a reset in between, the newest
+//! whole page is the store.
+//!
+//! `STORE_VERSION` is bumped when the meaning of a stored value changes, and `migrate` converts
+//! the pages written by the older versions.
+//!
+//! The store only needs a `NorFlash`, so that it is tested on the host against a RAM flash:
+//! `cargo make test-tools`.
+
+use embedded_storage_async::nor_flash::NorFlash;
+use heapless::Vec;
+
+/// Marks a settings page holding a store, "UKVS".
+const STORE_MAGIC: [u8; 4] = *b"UKVS";
+/// Version of the stored values, see `migrate`.
+const STORE_VERSION: u8 = 2;
+const HEADER_BYTES: usize = 8;
+const RECORD_HEADER_BYTES: usize = 4;
+/// Key read from the erased flash after the last record.
+const ERASED_KEY: u8 = 0xFF;
+pub const MAX_VALUE_BYTES: usize = 32;
+/// Bytes of the records kept when the page is written again.
+const COMPACTED_BYTES: usize = 512;
+/// Keys of the theme, rotation and idle timeout of `src/settings.rs`, the settings of version 1.
+const V1_KEYS: [u8; 3] = [1, 2, 3];
+
+/// A value the store can keep.
+pub trait SettingValue: Copy + PartialEq {
+    /// Length of the encoded value, at most `MAX_VALUE_BYTES`.
+    const SIZE: usize;
+    fn encode(self, bytes: &mut [u8]);
+    /// `None` for bytes which are not a value of the type, the default is read instead.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+/// A setting: its key in the store, never to be reused for another setting, and its default.
+pub struct Key<T> {
+    pub id: u8,
+    pub default: T,
+}
+
+fn checksum(id: u8, value: &[u8]) -> u8 {
+    value
+        .iter()
+        .fold(id ^ 0x5A, |sum, &byte| sum.rotate_left(1) ^ byte)
+}
+
+/// Record of `value`, padded to a word.
+fn record(id: u8, value: &[u8]) -> Vec<u8, { RECORD_HEADER_BYTES + MAX_VALUE_BYTES }> {
+    let mut record = Vec::new();
+    let _ = record.extend_from_slice(&[id, value.len() as u8, checksum(id, value), 0]);
+    let _ = record.extend_from_slice(value);
+    while record.len() % 4 != 0 {
+        let _ = record.push(0xFF);
+    }
+    record
+}
+
+/// Sequence number of a page holding a store of this version.
+fn sequence(header: &[u8; HEADER_BYTES]) -> Option<u16> {
+    let [m0, m1, m2, m3, version, s0, s1, _] = *header;
+    ([m0, m1, m2, m3] == STORE_MAGIC && version == STORE_VERSION)
+        .then(|| u16::from_le_bytes([s0, s1]))
+}
+
+pub struct SettingsStore<F> {
+    flash: F,
+    /// Addresses of the two pages the store alternates between.
+    pages: [u32; 2],
+    /// Address of the page holding the store.
+    page: u32,
+    /// Sequence number of the page, the next page gets the following one.
+    sequence: u16,
+    /// Offset of the first free byte of the page.
+    end: usize,
+}
+
+impl<F: NorFlash> SettingsStore<F> {
+    /// Open the store of the newest of `pages`, migrating the pages of the older versions and
+    /// formatting a page if none holds a store.
+    pub async fn open(flash: F, pages: [u32; 2]) -> Result<Self, F::Error> {
+        let mut store = Self {
+            flash,
+            pages,
+            page: pages[0],
+            sequence: 0,
+            end: HEADER_BYTES,
+        };
+        let mut headers = [[0; HEADER_BYTES]; 2];
+        for (page, header) in pages.into_iter().zip(&mut headers) {
+            store.flash.read(page, header).await?;
+        }
+        // The sequence numbers wrap, the newest page is the one just after the other
+        let current = match headers.each_ref().map(sequence) {
+            [Some(first), Some(second)] if second.wrapping_sub(first) as i16 > 0 => {
+                Some((1, second))
+            }
+            [Some(first), _] => Some((0, first)),
+            [None, second] => second.map(|second| (1, second)),
+        };
+        if let Some((index, sequence)) = current {
+            store.page = pages[index];
+            store.sequence = sequence;
+            store.end = store.scan(|_, _| {}).await?;
+        } else if !store.migrate(headers).await? {
+            store.write_page(&[]).await?;
+        }
+        Ok(store)
+    }
+
+    /// Convert a page written by an older version, returns whether there was one.
+    async fn migrate(&mut self, headers: [[u8; HEADER_BYTES]; 2]) -> Result<bool, F::Error> {
+        for (page, header) in self.pages.into_iter().zip(headers) {
+            // Version 1, before the store: a single record of the theme, rotation and idle timeout,
+            // in what is now the second page
+            if let [b'U', b'R', b'C', b'H', 1, theme, rotation, idle_timeout] = header {
+                let mut records = Vec::<u8, COMPACTED_BYTES>::new();
+                for (id, value) in V1_KEYS.into_iter().zip([theme, rotation, idle_timeout]) {
+                    let _ = records.extend_from_slice(&record(id, &[value]));
+                }
+                self.page = page;
+                self.write_page(&records).await?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Call `f` with the key and value of each valid record, oldest first, returns the end of
+    /// the records.
+    async fn scan(&mut self, mut f: impl FnMut(u8, &[u8])) -> Result<usize, F::Error> {
+        let mut offset = HEADER_BYTES;
+        while offset + RECORD_HEADER_BYTES <= F::ERASE_SIZE {
+            let mut header = [0; RECORD_HEADER_BYTES];
+            self.flash
+                .read(self.page + offset as u32, &mut header)
+                .await?;
+            let [id, len, sum, _] = header;
+            let len = len as usize;
+            if id == ERASED_KEY
+                || len > MAX_VALUE_BYTES
+                || offset + RECORD_HEADER_BYTES + len > F::ERASE_SIZE
+            {
+                break;
+            }
+            let mut value = [0; MAX_VALUE_BYTES];
+            let value = &mut value[..len];
+            let value_addr = self.page + (offset + RECORD_HEADER_BYTES) as u32;
+            self.flash.read(value_addr, value).await?;
+            // A record cut by a reset is skipped
+            if checksum(id, value) == sum {
+                f(id, value);
+            }
+            offset += (RECORD_HEADER_BYTES + len).next_multiple_of(4);
+        }
+        Ok(offset)
+    }
+
+    /// The value of `key`, its default if it was never set or cannot be read.
+    pub async fn get<T: SettingValue>(&mut self, key: &Key<T>) -> T {
+        let mut value = None;
+        let scanned = self
+            .scan(|id, bytes| {
+                if id == key.id {
+                    value = (bytes.len() == T::SIZE).then(|| T::decode(bytes)).flatten();
+                }
+            })
+            .await;
+        // The records read before an error are older, not the current value
+        scanned.ok().and(value).unwrap_or(key.default)
+    }
+
+    /// Keep `value`, unless it is already the one read.
+    pub async fn set<T: SettingValue>(&mut self, key: &Key<T>, value: T) -> Result<(), F::Error> {
+        if self.get(key).await == value {
+            return Ok(());
+        }
+        let mut bytes = [0; MAX_VALUE_BYTES];
+        value.encode(&mut bytes[..T::SIZE]);
+        let record = record(key.id, &bytes[..T::SIZE]);
+        if self.end + record.len() > F::ERASE_SIZE {
+            return self.compact(key.id, &record).await;
+        }
+        self.flash
+            .write(self.page + self.end as u32, &record)
+            .await?;
+        self.end += record.len();
+        Ok(())
+    }
+
+    /// Move the store to the other page, with the last record of each key and `new_record` for
+    /// `new_id`.
+    async fn compact(&mut self, new_id: u8, new_record: &[u8]) -> Result<(), F::Error> {
+        let mut records = Vec::<u8, COMPACTED_BYTES>::new();
+        let mut latest = Vec::<(u8, Vec<u8, MAX_VALUE_BYTES>), 32>::new();
+        self.scan(|id, value| {
+            let value = Vec::from_slice(value).unwrap_or_default();
+            match latest.iter_mut().find(|(key, _)| *key == id) {
+                Some((_, latest_value)) => *latest_value = value,
+                None => {
+                    let _ = latest.push((id, value));
+                }
+            }
+        })
+        .await?;
+        // `COMPACTED_BYTES` holds 14 settings of the largest size, far more than there are
+        for (id, value) in &latest {
+            if *id != new_id {
+                let _ = records.extend_from_slice(&record(*id, value));
+            }
+        }
+        let _ = records.extend_from_slice(new_record);
+        self.write_page(&records).await
+    }
+
+    /// Write `records` and the header to the other page, then erase the current one: a reset in
+    /// between leaves one of them whole.
+    async fn write_page(&mut self, records: &[u8]) -> Result<(), F::Error> {
+        let spare = if self.page == self.pages[0] {
+            self.pages[1]
+        } else {
+            self.pages[0]
+        };
+        let sequence = self.sequence.wrapping_add(1);
+        let page_bytes = F::ERASE_SIZE as u32;
+        self.flash.erase(spare, spare + page_bytes).await?;
+        if !records.is_empty() {
+            self.flash
+                .write(spare + HEADER_BYTES as u32, records)
+                .await?;
+        }
+        // The header commits the page
+        let [m0, m1, m2, m3] = STORE_MAGIC;
+        let [s0, s1] = sequence.to_le_bytes();
+        let header = [m0, m1, m2, m3, STORE_VERSION, s0, s1, 0xFF];
+        self.flash.write(spare, &header).await?;
+        let old_page = self.page;
+        self.page = spare;
+        self.sequence = sequence;
+        self.end = HEADER_BYTES + records.len();
+        // On failure, the old page stays behind the new one in the sequence
+        self.flash.erase(old_page, old_page + page_bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_storage_async::nor_flash::{
+        ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+    };
+
+    use super::*;
+
+    const PAGE_BYTES: usize = 4096;
+    const PAGES: [u32; 2] = [0xef000, 0xf0000];
+    const THEME: Key<u8> = Key { id: 1, default: 0 };
+    const ROTATION: Key<u8> = Key { id: 2, default: 0 };
+    const IDLE_TIMEOUT: Key<u8> = Key { id: 3, default: 2 };
+
+    impl SettingValue for u8 {
+        const SIZE: usize = 1;
+
+        fn encode(self, bytes: &mut [u8]) {
+            bytes[0] = self;
+        }
+
+        fn decode(bytes: &[u8]) -> Option<Self> {
+            Some(bytes[0])
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct WriteFailed;
+
+    impl NorFlashError for WriteFailed {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::Other
+        }
+    }
+
+    /// The two settings pages in RAM, a write only clears bits like the NVMC.
+    struct RamFlash<'a> {
+        bytes: &'a mut [u8],
+        fail_writes: bool,
+    }
+
+    impl<'a> RamFlash<'a> {
+        fn new(bytes: &'a mut [u8]) -> Self {
+            Self {
+                bytes,
+                fail_writes: false,
+            }
+        }
+
+        fn offset(addr: u32) -> usize {
+            (addr - PAGES[0]) as usize
+        }
+    }
+
+    impl ErrorType for RamFlash<'_> {
+        type Error = WriteFailed;
+    }
+
+    impl ReadNorFlash for RamFlash<'_> {
+        const READ_SIZE: usize = 1;
+
+        async fn read(&mut self, addr: u32, bytes: &mut [u8]) -> Result<(), WriteFailed> {
+            let offset = Self::offset(addr);
+            bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for RamFlash<'_> {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = PAGE_BYTES;
+
+        async fn erase(&mut self, from: u32, to: u32) -> Result<(), WriteFailed> {
+            self.bytes[Self::offset(from)..Self::offset(to)].fill(0xFF);
+            Ok(())
+        }
+
+        async fn write(&mut self, addr: u32, bytes: &[u8]) -> Result<(), WriteFailed> {
+            if self.fail_writes {
+                return Err(WriteFailed);
+            }
+            let offset = Self::offset(addr);
+            for (flash, byte) in self.bytes[offset..].iter_mut().zip(bytes) {
+                *flash &= byte;
+            }
+            Ok(())
+        }
+    }
+
+    fn erased() -> Vec<u8, { 2 * PAGE_BYTES }> {
+        let mut bytes = Vec::new();
+        bytes.resize(2 * PAGE_BYTES, 0xFF).unwrap();
+        bytes
+    }
+
+    fn open(bytes: &mut [u8]) -> SettingsStore<RamFlash<'_>> {
+        block_on(SettingsStore::open(RamFlash::new(bytes), PAGES)).unwrap()
+    }
+
+    /// Write a store of `sequence` holding `records` in `page`, as `write_page` does.
+    fn write_store(bytes: &mut [u8], page: usize, sequence: u16, records: &[(u8, u8)]) {
+        let page = &mut bytes[page * PAGE_BYTES..(page + 1) * PAGE_BYTES];
+        page.fill(0xFF);
+        let [s0, s1] = sequence.to_le_bytes();
+        page[..HEADER_BYTES].copy_from_slice(&[
+            b'U',
+            b'K',
+            b'V',
+            b'S',
+            STORE_VERSION,
+            s0,
+            s1,
+            0xFF,
+        ]);
+        let mut offset = HEADER_BYTES;
+        for &(id, value) in records {
+            let record = record(id, &[value]);
+            page[offset..offset + record.len()].copy_from_slice(&record);
+            offset += record.len();
+        }
+    }
+
+    fn is_erased(bytes: &[u8], page: usize) -> bool {
+        bytes[page * PAGE_BYTES..(page + 1) * PAGE_BYTES]
+            .iter()
+            .all(|&byte| byte == 0xFF)
+    }
+
+    #[test]
+    fn defaults_then_values_across_opens() {
+        let mut bytes = erased();
+        let mut store = open(&mut bytes);
+        assert_eq!(block_on(store.get(&THEME)), 0);
+        assert_eq!(block_on(store.get(&IDLE_TIMEOUT)), 2);
+        block_on(store.set(&THEME, 1)).unwrap();
+        block_on(store.set(&ROTATION, 1)).unwrap();
+        let mut store = open(&mut bytes);
+        assert_eq!(block_on(store.get(&THEME)), 1);
+        assert_eq!(block_on(store.get(&ROTATION)), 1);
+        assert_eq!(block_on(store.get(&IDLE_TIMEOUT)), 2);
+    }
+
+    #[test]
+    fn full_page_is_compacted_to_the_other() {
+        let mut bytes = erased();
+        let mut store = open(&mut bytes);
+        let first_page = store.page;
+        block_on(store.set(&ROTATION, 1)).unwrap();
+        // 8 bytes a record, a page holds about 500
+        for value in 0..600 {
+            block_on(store.set(&THEME, value as u8 % 2 + 1)).unwrap();
+        }
+        assert_ne!(store.page, first_page);
+        assert!(store.end < PAGE_BYTES / 2);
+        let mut store = open(&mut bytes);
+        assert_eq!(block_on(store.get(&THEME)), 2);
+        assert_eq!(block_on(store.get(&ROTATION)), 1);
+        // Only one page holds the store, the full one was erased
+        let current = PAGES.iter().position(|&page| page == store.page).unwrap();
+        assert!(is_erased(&bytes, 1 - current));
+    }
+
+    #[test]
+    fn newest_page_wins_across_the_sequence_wrap() {
+        // A reset between the header of the new page and the erase of the old one
+        let mut bytes = erased();
+        write_store(&mut bytes, 0, u16::MAX, &[(THEME.id, 1)]);
+        write_store(&mut bytes, 1, 0, &[(THEME.id, 2)]);
+        let mut store = open(&mut bytes);
+        assert_eq!((store.page, store.sequence), (PAGES[1], 0));
+        assert_eq!(block_on(store.get(&THEME)), 2);
+
+        write_store(&mut bytes, 0, 0, &[(THEME.id, 2)]);
+        write_store(&mut bytes, 1, u16::MAX, &[(THEME.id, 1)]);
+        let mut store = open(&mut bytes);
+        assert_eq!((store.page, store.sequence), (PAGES[0], 0));
+        assert_eq!(block_on(store.get(&THEME)), 2);
+        // The next page follows the wrapped sequence
+        for value in 0..600 {
+            block_on(store.set(&THEME, value as u8 % 2 + 1)).unwrap();
+        }
+        assert_eq!(store.page, PAGES[1]);
+        assert_eq!(store.sequence, 1);
+    }
+
+    #[test]
+    fn torn_record_is_skipped() {
+        let mut bytes = erased();
+        write_store(&mut bytes, 0, 1, &[(THEME.id, 1), (THEME.id, 2)]);
+        // The value of the last record was cut by a reset
+        let last_value = HEADER_BYTES + 8 + RECORD_HEADER_BYTES;
+        bytes[last_value] = 0xFF;
+        let mut store = open(&mut bytes);
+        assert_eq!(block_on(store.get(&THEME)), 1);
+        // The next record goes after it
+        block_on(store.set(&ROTATION, 1)).unwrap();
+        let mut store = open(&mut bytes);
+        assert_eq!(store.end, HEADER_BYTES + 3 * 8);
+        assert_eq!(block_on(store.get(&THEME)), 1);
+        assert_eq!(block_on(store.get(&ROTATION)), 1);
+    }
+
+    #[test]
+    fn version_1_is_migrated() {
+        let mut bytes = erased();
+        bytes[PAGE_BYTES..PAGE_BYTES + HEADER_BYTES]
+            .copy_from_slice(&[b'U', b'R', b'C', b'H', 1, 1, 1, 3]);
+        let mut store = open(&mut bytes);
+        assert_eq!(block_on(store.get(&THEME)), 1);
+        assert_eq!(block_on(store.get(&ROTATION)), 1);
+        assert_eq!(block_on(store.get(&IDLE_TIMEOUT)), 3);
+        // Moved to the first page, the old one erased
+        assert_eq!(store.page, PAGES[0]);
+        assert!(is_erased(&bytes, 1));
+        let mut store = open(&mut bytes);
+        assert_eq!(block_on(store.get(&IDLE_TIMEOUT)), 3);
+    }
+
+    #[test]
+    fn failed_write_keeps_the_end() {
+        let mut bytes = erased();
+        let mut store = open(&mut bytes);
+        block_on(store.set(&THEME, 1)).unwrap();
+        let end = store.end;
+        store.flash.fail_writes = true;
+        assert_eq!(block_on(store.set(&ROTATION, 1)), Err(WriteFailed));
+        assert_eq!(store.end, end);
+        store.flash.fail_writes = false;
+        block_on(store.set(&ROTATION, 1)).unwrap();
+        let mut store = open(&mut bytes);
+        assert_eq!(store.end, end + 8);
+        assert_eq!(block_on(store.get(&THEME)), 1);
+        assert_eq!(block_on(store.get(&ROTATION)), 1);
+    }
+}
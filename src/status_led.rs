@@ -0,0 +1,103 @@
+//! Blink codes of the onboard LED, the status output of the builds without a display.
+
+use embassy_nrf::gpio::{Level, Output};
+use embassy_time::{Duration, Timer};
+use rmk::{channel::ControllerSub, controller::Controller, event::ControllerEvent};
+
+use crate::{
+    config::STATUS_LED_ACTIVE_HIGH,
+    crash::{save_crash_report, CrashReport},
+    watchdog::ResetReason,
+};
+
+const BLINK_ON: Duration = Duration::from_millis(150);
+const BLINK_OFF: Duration = Duration::from_millis(250);
+/// Level of the LED pin when the LED is off.
+pub const LED_OFF: Level = if STATUS_LED_ACTIVE_HIGH {
+    Level::Low
+} else {
+    Level::High
+};
+const LED_ON: Level = if STATUS_LED_ACTIVE_HIGH {
+    Level::High
+} else {
+    Level::Low
+};
+/// Battery level under which the half warns once.
+const LOW_BATTERY_PERCENT: u8 = 10;
+
+/// Number of blinks of each status.
+#[derive(Clone, Copy)]
+pub enum BlinkCode {
+    /// The half started.
+    Boot = 1,
+    /// The half started after a notable reset, see `ResetReason::is_notable`.
+    Reset = 2,
+    /// The half started after a crash, the report is in the crash log.
+    Crash = 3,
+    /// The battery went under `LOW_BATTERY_PERCENT`.
+    LowBattery = 4,
+}
+
+impl BlinkCode {
+    /// Code shown at boot.
+    pub fn at_boot(reset_reason: ResetReason, crash_report: Option<&CrashReport>) -> Self {
+        if crash_report.is_some() {
+            BlinkCode::Crash
+        } else if reset_reason.is_notable() {
+            BlinkCode::Reset
+        } else {
+            BlinkCode::Boot
+        }
+    }
+}
+
+pub enum StatusLedEvent {
+    Controller(ControllerEvent),
+    Blink(BlinkCode),
+}
+
+pub struct StatusLed<'a> {
+    pub sub: ControllerSub,
+    pub led: Output<'a>,
+    /// Code waiting to be blinked, the boot one at first.
+    pub pending: Option<BlinkCode>,
+    pub low_battery: bool,
+}
+
+impl StatusLed<'_> {
+    async fn blink(&mut self, code: BlinkCode) {
+        for _ in 0..code as u8 {
+            self.led.set_level(LED_ON);
+            Timer::after(BLINK_ON).await;
+            self.led.set_level(LED_OFF);
+            Timer::after(BLINK_OFF).await;
+        }
+    }
+}
+
+impl Controller for StatusLed<'_> {
+    type Event = StatusLedEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            StatusLedEvent::Blink(code) => self.blink(code).await,
+            StatusLedEvent::Controller(ControllerEvent::Battery(percent)) => {
+                let low_battery = percent < LOW_BATTERY_PERCENT;
+                if low_battery && !self.low_battery {
+                    self.blink(BlinkCode::LowBattery).await;
+                }
+                self.low_battery = low_battery;
+            }
+            StatusLedEvent::Controller(_) => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        save_crash_report().await;
+        match self.pending.take() {
+            Some(code) => StatusLedEvent::Blink(code),
+            None => StatusLedEvent::Controller(self.sub.next_message_pure().await),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! The defmt logs on a USB serial port of the central, with the `usb-log` feature.
+//!
+//! RMK's `usb_log` adds a CDC-ACM serial port to its USB device, and writes the records of the
+//! `log` crate there, one per line. The defmt logger copies each frame, which this controller
+//! writes as a line of its own: `defmt:` and the encoded frame in hex. The `defmt-usb` host tool
+//! decodes these lines with the defmt table of the firmware ELF, and prints the others as they are.
+//!
+//! Without the feature, the controller waits forever: RMK's macros take every controller.
+
+use defmt_rtt::{next_frame, Frame};
+use rmk::controller::Controller;
+
+pub struct UsbLog;
+
+impl Controller for UsbLog {
+    type Event = Frame;
+
+    async fn process_event(&mut self, frame: Self::Event) {
+        #[cfg(feature = "usb-log")]
+        log::info!("defmt:{}", Hex(&frame));
+        #[cfg(not(feature = "usb-log"))]
+        let _ = frame;
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        next_frame().await
+    }
+}
+
+/// The bytes in lowercase hex, two digits each.
+#[cfg(feature = "usb-log")]
+struct Hex<'a>(&'a [u8]);
+
+#[cfg(feature = "usb-log")]
+impl core::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
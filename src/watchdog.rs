@@ -0,0 +1,199 @@
+//! Hardware watchdog, and the reason of the last reset.
+//!
+//! The watchdog has one handle per supervised task, the status controller, the log controller,
+//! the battery monitor and the events of RMK: it resets the half unless each of them is fed, so a
+//! single stuck task is enough.
+
+use core::{cell::RefCell, mem::MaybeUninit, pin::pin, ptr::addr_of_mut};
+
+use cortex_m::peripheral::SCB;
+use embassy_futures::select::{select, Either};
+use embassy_nrf::{
+    pac,
+    peripherals::WDT,
+    wdt::{self, WatchdogHandle},
+    Peri,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    once_lock::OnceLock,
+};
+use embassy_time::{Duration, Timer};
+use heapless::Vec;
+use rmk::{channel::ControllerSub, controller::Controller, event::ControllerEvent};
+
+use crate::unwrap;
+
+/// Time without feeding after which the watchdog resets the half.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(10);
+pub const WATCHDOG_FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ResetReason {
+    PowerOn,
+    /// Reset pin, the button of the half.
+    Pin,
+    Watchdog,
+    /// Software reset, after a crash or from the bootloader.
+    SoftReset,
+    Lockup,
+    /// Wake up from System OFF, the deep sleep of RMK.
+    Wake,
+    /// Restart after the stored keymap was cleared for a new default keymap.
+    NewKeymap,
+}
+
+impl ResetReason {
+    pub fn label(self) -> &'static str {
+        match self {
+            ResetReason::PowerOn => "allumage",
+            ResetReason::Pin => "bouton",
+            ResetReason::Watchdog => "watchdog",
+            ResetReason::SoftReset => "logiciel",
+            ResetReason::Lockup => "blocage CPU",
+            ResetReason::Wake => "fin dodo",
+            ResetReason::NewKeymap => "nouveau keymap",
+        }
+    }
+
+    /// Whether the reset is worth a message on the screen.
+    pub fn is_notable(self) -> bool {
+        !matches!(self, ResetReason::PowerOn | ResetReason::Wake)
+    }
+}
+
+static RESET_REASON: OnceLock<ResetReason> = OnceLock::new();
+/// Marks the reason of a restart of the firmware, "RS" and the reason in the low byte.
+const RESTART_MAGIC: u32 = 0x5253_0000;
+/// Reason of the last `restart`, kept across the reset, garbage after a power-on.
+#[link_section = ".retained"]
+static mut RESTART_REASON: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Restart the half, the next `reset_reason` being `reason` instead of a software reset.
+pub fn restart(reason: ResetReason) -> ! {
+    let retained = unsafe { addr_of_mut!(RESTART_REASON) }.cast::<u32>();
+    unsafe { retained.write_volatile(RESTART_MAGIC | reason as u32) };
+    SCB::sys_reset()
+}
+
+fn take_restart_reason() -> Option<ResetReason> {
+    let retained = unsafe { addr_of_mut!(RESTART_REASON) }.cast::<u32>();
+    let marker = unsafe { retained.read_volatile() };
+    unsafe { retained.write_volatile(0) };
+    (marker == RESTART_MAGIC | ResetReason::NewKeymap as u32).then_some(ResetReason::NewKeymap)
+}
+
+/// Reason of the last reset, from `RESETREAS`.
+///
+/// The register accumulates the reasons until cleared, it is read and cleared on the first call.
+pub fn reset_reason() -> ResetReason {
+    *RESET_REASON.get_or_init(|| {
+        let resetreas = pac::POWER.resetreas().read();
+        let restart_reason = take_restart_reason();
+        let reason = if resetreas.dog() {
+            ResetReason::Watchdog
+        } else if resetreas.lockup() {
+            ResetReason::Lockup
+        } else if resetreas.sreq() {
+            restart_reason.unwrap_or(ResetReason::SoftReset)
+        } else if resetreas.resetpin() {
+            ResetReason::Pin
+        } else if resetreas.off() || resetreas.lpcomp() || resetreas.dif() || resetreas.vbus() {
+            ResetReason::Wake
+        } else {
+            ResetReason::PowerOn
+        };
+        pac::POWER.resetreas().write_value(resetreas);
+        defmt::info!(
+            "Reset reason: {} (RESETREAS={=u32:#x})",
+            reason.label(),
+            resetreas.0
+        );
+        reason
+    })
+}
+
+/// Watchdog running while the CPU sleeps, and paused while a debug probe halts it.
+fn watchdog_config() -> wdt::Config {
+    let mut config = wdt::Config::default();
+    // The watchdog counts the 32.768 kHz clock
+    config.timeout_ticks = 32_768 * WATCHDOG_TIMEOUT.as_secs() as u32;
+    config.action_during_sleep = wdt::SleepConfig::RUN;
+    config.action_during_debug_halt = wdt::HaltConfig::PAUSE;
+    config
+}
+
+/// Most supervised tasks of a half, the watchdog has 8 handles.
+const MAX_SUPERVISED: usize = 4;
+
+/// Handles of the watchdog not taken by a task yet.
+static HANDLES: Mutex<CriticalSectionRawMutex, RefCell<Vec<WatchdogHandle, MAX_SUPERVISED>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Start the watchdog with a handle for each of the `N` supervised tasks, before they take them.
+pub fn start_watchdog<const N: usize>(wdt: Peri<'static, WDT>) {
+    let (_watchdog, handles) = unwrap!(wdt::Watchdog::try_new::<N>(wdt, watchdog_config()).ok());
+    HANDLES.lock(|free| {
+        for handle in handles {
+            unwrap!(free.borrow_mut().push(handle).ok());
+        }
+    });
+}
+
+/// Take the handle of a supervised task, panics when the handles of `start_watchdog` are all taken.
+pub fn watchdog_handle() -> WatchdogHandle {
+    unwrap!(HANDLES.lock(|free| free.borrow_mut().pop()))
+}
+
+/// An event controller feeding its own handle of the watchdog: every second while it waits for
+/// an event, and after handling each one. Handling an event which never ends, like an endless
+/// wait for the flash, resets the half.
+pub struct Supervised<C> {
+    controller: C,
+    handle: WatchdogHandle,
+}
+
+impl<C> Supervised<C> {
+    pub fn new(controller: C) -> Self {
+        Self {
+            controller,
+            handle: watchdog_handle(),
+        }
+    }
+}
+
+impl<C: Controller> Controller for Supervised<C> {
+    type Event = C::Event;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        self.controller.process_event(event).await;
+        self.handle.pet();
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        // The wait for the event goes on across the feeds
+        let mut next_message = pin!(self.controller.next_message());
+        loop {
+            match select(&mut next_message, Timer::after(WATCHDOG_FEED_INTERVAL)).await {
+                Either::First(event) => return event,
+                Either::Second(()) => self.handle.pet(),
+            }
+        }
+    }
+}
+
+/// Takes the events of RMK, supervised: it stops feeding when RMK stalls the executor. Without
+/// events, `Supervised` still feeds it while it waits, a quiet RMK is not a dead one.
+pub struct RmkEvents {
+    pub sub: ControllerSub,
+}
+
+impl Controller for RmkEvents {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, _event: Self::Event) {}
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
@@ -0,0 +1,154 @@
+//! Print the defmt logs of the central from its USB serial port, with the `usb-log` feature.
+//!
+//! The lines of the defmt frames, `defmt:` and the encoded frame in hex, are decoded by
+//! `defmt-print` with the defmt table of the firmware ELF, which must be the one flashed. The
+//! other lines, the logs of RMK, are printed as they are:
+//!
+//! ```sh
+//! defmt-usb
+//! defmt-usb --elf target/thumbv7em-none-eabihf/release/central /dev/ttyACM1
+//! ```
+//!
+//! `defmt-print` is installed with `cargo install defmt-print`. The lines are written by
+//! `src/usb_log.rs`.
+
+use std::{
+    env,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    process::{Command, ExitCode, Stdio},
+};
+
+const DEFAULT_ELF: &str = "target/thumbv7em-none-eabihf/release/central";
+const DEFAULT_PORT: &str = "/dev/ttyACM0";
+const FRAME_PREFIX: &str = "defmt:";
+
+/// A line read from the serial port.
+#[derive(Debug, PartialEq)]
+enum Line<'a> {
+    /// An encoded defmt frame, ending with its 0 delimiter.
+    Frame(Vec<u8>),
+    /// A frame cut short, when the firmware's USB buffer was full.
+    Cut,
+    Text(&'a str),
+}
+
+fn parse_line(line: &str) -> Line<'_> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    // After the prefix, if the logger adds one
+    let Some((_, hex)) = line.split_once(FRAME_PREFIX) else {
+        return Line::Text(line);
+    };
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect();
+    // A frame has a single 0, at its end
+    match bytes {
+        Some(bytes)
+            if bytes
+                .iter()
+                .position(|&byte| byte == 0)
+                .is_some_and(|end| end + 1 == bytes.len()) =>
+        {
+            Line::Frame(bytes)
+        }
+        _ => Line::Cut,
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut elf = DEFAULT_ELF.to_string();
+    let mut port = DEFAULT_PORT.to_string();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--elf" => elf = args.next().ok_or("--elf needs the firmware ELF")?,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => port = arg,
+        }
+    }
+
+    // Raw bytes, without echo back to the keyboard
+    let stty_port = if cfg!(target_os = "macos") {
+        "-f"
+    } else {
+        "-F"
+    };
+    let stty = Command::new("stty")
+        .args([stty_port, &port, "raw", "-echo"])
+        .status()
+        .map_err(|e| format!("cannot run stty: {e}"))?;
+    if !stty.success() {
+        return Err(format!("cannot set {port} to raw mode"));
+    }
+    let serial = File::open(&port).map_err(|e| format!("cannot open {port}: {e}"))?;
+
+    let mut decoder = Command::new("defmt-print")
+        .args(["-e", &elf])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            format!("cannot run defmt-print: {e}, install it with `cargo install defmt-print`")
+        })?;
+    let mut frames = decoder.stdin.take().unwrap();
+
+    for line in BufReader::new(serial).lines() {
+        let line = line.map_err(|e| format!("cannot read {port}: {e}"))?;
+        match parse_line(&line) {
+            Line::Frame(bytes) => frames
+                .write_all(&bytes)
+                .map_err(|e| format!("defmt-print stopped: {e}"))?,
+            Line::Cut => eprintln!("defmt-usb: frame cut short, skipped"),
+            Line::Text("") => {}
+            Line::Text(text) => println!("{text}"),
+        }
+    }
+    Err(format!("{port} closed"))
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("defmt-usb: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame() {
+        assert_eq!(
+            parse_line("defmt:0a01ff00\r\n"),
+            Line::Frame(vec![0x0a, 0x01, 0xff, 0x00])
+        );
+        assert_eq!(parse_line("INFO defmt:0100"), Line::Frame(vec![0x01, 0x00]));
+    }
+
+    #[test]
+    fn cut_frame() {
+        // Without its delimiter
+        assert_eq!(parse_line("defmt:0a01ff"), Line::Cut);
+        // Half a byte
+        assert_eq!(parse_line("defmt:0a010"), Line::Cut);
+        // Not hex
+        assert_eq!(parse_line("defmt:0a0g00"), Line::Cut);
+        // The end of a frame and another one
+        assert_eq!(parse_line("defmt:0a000b00"), Line::Cut);
+        assert_eq!(parse_line("defmt:"), Line::Cut);
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            parse_line("Connected to profile 0\r\n"),
+            Line::Text("Connected to profile 0")
+        );
+        assert_eq!(parse_line(""), Line::Text(""));
+    }
+}
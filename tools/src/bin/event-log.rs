@@ -0,0 +1,417 @@
+//! Print the flash event log of the central half from a flash dump.
+//!
+//! The dump is either the `CURRENT.UF2` file of the bootloader drive, or a raw binary read with a
+//! probe, given with the flash address it starts at:
+//!
+//! ```sh
+//! event-log CURRENT.UF2
+//! event-log --base 0xea000 dump.bin
+//! ```
+//!
+//! The layout of the log and the layer names come from `keyboard.toml`, which must be the one
+//! the firmware was built with. The records are described in `src/flash_log.rs`.
+
+use std::{collections::HashMap, env, fs, process::ExitCode};
+
+use board::BoardProfile;
+use memory::{FlashLayout, PAGE_SIZE};
+
+// The flash layout of the firmware, from its build script
+#[allow(dead_code)]
+#[path = "../../../build/board.rs"]
+mod board;
+#[allow(dead_code)]
+#[path = "../../../build/memory.rs"]
+mod memory;
+
+const FLASH_LOG_MAGIC: u32 = u32::from_le_bytes(*b"ULOG");
+const HEADER_BYTES: usize = 8;
+const RECORD_BYTES: usize = 8;
+const ERASED_TAG: u8 = 0xFF;
+
+const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+const UF2_BLOCK_BYTES: usize = 512;
+
+/// Flash contents known from the dump, byte by byte.
+struct Flash(HashMap<u32, u8>);
+
+impl Flash {
+    fn from_raw(bytes: &[u8], base: u32) -> Self {
+        Self((base..).zip(bytes.iter().copied()).collect())
+    }
+
+    fn from_uf2(bytes: &[u8]) -> Result<Self, String> {
+        let mut flash = HashMap::new();
+        for (index, block) in bytes.chunks(UF2_BLOCK_BYTES).enumerate() {
+            let word =
+                |offset: usize| u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap());
+            if block.len() != UF2_BLOCK_BYTES
+                || word(0) != UF2_MAGIC_START0
+                || word(4) != UF2_MAGIC_START1
+                || word(UF2_BLOCK_BYTES - 4) != UF2_MAGIC_END
+            {
+                return Err(format!("UF2 block {index} is invalid"));
+            }
+            let addr = word(12);
+            let size = word(16) as usize;
+            if size > 476 {
+                return Err(format!("UF2 block {index} has {size} bytes of payload"));
+            }
+            flash.extend((addr..).zip(block[32..32 + size].iter().copied()));
+        }
+        Ok(Self(flash))
+    }
+
+    fn read(&self, addr: u32, len: u32) -> Result<Vec<u8>, String> {
+        (addr..addr + len)
+            .map(|addr| self.0.get(&addr).copied())
+            .collect::<Option<_>>()
+            .ok_or_else(|| {
+                format!(
+                    "the dump does not cover the event log, at {addr:#x}..{:#x}",
+                    addr + len
+                )
+            })
+    }
+}
+
+/// What the tool needs from `keyboard.toml`.
+struct Layout {
+    event_log_addr: u32,
+    event_log_pages: u32,
+    layer_names: Vec<String>,
+}
+
+impl Layout {
+    fn from_keyboard_toml(path: &str) -> Result<Self, String> {
+        let keyboard_toml: toml::Table = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {path}: {e}"))?
+            .parse()
+            .map_err(|e| format!("cannot parse {path}: {e}"))?;
+        let profile = BoardProfile::selected(&keyboard_toml)?;
+        // Same regions as the firmware, which the build script checked
+        let flash_layout = FlashLayout::from_keyboard_toml(&keyboard_toml, profile);
+        let event_log = flash_layout.region("EVENT_LOG");
+        let layer_names = keyboard_toml
+            .get("layer")
+            .and_then(toml::Value::as_array)
+            .map(|layers| {
+                layers
+                    .iter()
+                    .map(|layer| {
+                        layer
+                            .get("name")
+                            .and_then(toml::Value::as_str)
+                            .unwrap_or_default()
+                            .to_string()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            event_log_addr: event_log.start,
+            event_log_pages: (event_log.end - event_log.start) / PAGE_SIZE,
+            layer_names,
+        })
+    }
+}
+
+/// Format a record like the event log page of the central screen.
+fn describe(layout: &Layout, tag: u8, payload: [u8; 3]) -> String {
+    let yes_no = |value: u8| if value != 0 { "oui" } else { "non" };
+    match tag {
+        0 => {
+            // `ResetReason` of the firmware
+            let reason = match payload[0] {
+                0 => "allumage",
+                1 => "bouton",
+                2 => "watchdog",
+                3 => "logiciel",
+                4 => "blocage CPU",
+                5 => "fin dodo",
+                6 => "nouveau keymap",
+                _ => "?",
+            };
+            format!("boot, reset {reason}")
+        }
+        1 => format!("bat {}%", payload[0]),
+        2 => format!("charge {}", yes_no(payload[0])),
+        3 => match layout.layer_names.get(payload[0] as usize) {
+            Some(name) if !name.is_empty() => format!("layer {name}"),
+            _ => format!("layer {}", payload[0]),
+        },
+        4 => format!("conn {}", if payload[0] == 0 { "USB" } else { "BLE" }),
+        5 => format!("peri {} {}", payload[0], yes_no(payload[1])),
+        6 => format!("central {}", yes_no(payload[0])),
+        7 => format!("dodo {}", yes_no(payload[0])),
+        8 => {
+            let state = match payload[1] {
+                0 => "none",
+                1 => "advr",
+                2 => "conn",
+                _ => "?",
+            };
+            format!("prof {} {state}", payload[0])
+        }
+        9 => format!("prof {}", payload[0]),
+        10 => "clear peer".to_string(),
+        _ => format!("inconnu {tag} {payload:02x?}"),
+    }
+}
+
+fn format_time(millis: u32) -> String {
+    let secs = millis / 1000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        millis % 1000
+    )
+}
+
+/// Lines of the log, the sessions in the order of their pages.
+fn log_lines(flash: &Flash, layout: &Layout) -> Result<Vec<String>, String> {
+    let mut pages = Vec::new();
+    for page in 0..layout.event_log_pages {
+        let bytes = flash.read(layout.event_log_addr + page * PAGE_SIZE, PAGE_SIZE)?;
+        let magic = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        let sequence = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if magic == FLASH_LOG_MAGIC {
+            pages.push((sequence, bytes));
+        }
+    }
+    if pages.is_empty() {
+        return Err("the event log is empty".to_string());
+    }
+    pages.sort_by_key(|(sequence, _)| *sequence);
+
+    let mut lines = Vec::new();
+    let mut boots = 0;
+    for (_, bytes) in &pages {
+        for &[tag, p0, p1, p2, t0, t1, t2, t3] in
+            bytes[HEADER_BYTES..].as_chunks::<RECORD_BYTES>().0
+        {
+            if tag == ERASED_TAG {
+                break;
+            }
+            if tag == 0 {
+                boots += 1;
+                lines.push(format!("--- boot {boots} ---"));
+            }
+            let millis = u32::from_le_bytes([t0, t1, t2, t3]);
+            lines.push(format!(
+                "{}  {}",
+                format_time(millis),
+                describe(layout, tag, [p0, p1, p2])
+            ));
+        }
+    }
+    Ok(lines)
+}
+
+fn parse_addr(addr: &str) -> Result<u32, String> {
+    let parsed = match addr.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => addr.parse(),
+    };
+    parsed.map_err(|_| format!("invalid address {addr}"))
+}
+
+fn run() -> Result<(), String> {
+    let mut keyboard_toml = "keyboard.toml".to_string();
+    let mut base = None;
+    let mut dump = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keyboard-toml" => keyboard_toml = args.next().ok_or("missing keyboard.toml path")?,
+            "--base" => base = Some(parse_addr(&args.next().ok_or("missing base address")?)?),
+            _ if dump.is_none() => dump = Some(arg),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+    let Some(dump) = dump else {
+        return Err(
+            "usage: event-log [--keyboard-toml PATH] [--base ADDR] <CURRENT.UF2 | dump.bin>"
+                .to_string(),
+        );
+    };
+
+    let layout = Layout::from_keyboard_toml(&keyboard_toml)?;
+    let bytes = fs::read(&dump).map_err(|e| format!("cannot read {dump}: {e}"))?;
+    let flash = match base {
+        Some(base) => Flash::from_raw(&bytes, base),
+        None if dump.to_lowercase().ends_with(".uf2") => Flash::from_uf2(&bytes)?,
+        None => Flash::from_raw(&bytes, layout.event_log_addr),
+    };
+    for line in log_lines(&flash, &layout)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("event-log: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rmk::{ble::BleState, event::ControllerEvent};
+    use urchin_log_format::format_event;
+
+    use super::*;
+
+    const BASE: u32 = 0xea000;
+
+    fn layout() -> Layout {
+        Layout {
+            event_log_addr: BASE,
+            event_log_pages: 2,
+            layer_names: ["base", "nav", "prog", "peri"].map(String::from).to_vec(),
+        }
+    }
+
+    /// Two pages of the ring, the second one erased, the first one holding `records` of
+    /// `(tag, payload, millis)`.
+    fn log_pages(records: &[(u8, [u8; 3], u32)]) -> Vec<u8> {
+        let mut bytes = vec![0xFF; 2 * PAGE_SIZE as usize];
+        bytes[0..4].copy_from_slice(&FLASH_LOG_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+        for (index, (tag, payload, millis)) in records.iter().enumerate() {
+            let offset = HEADER_BYTES + index * RECORD_BYTES;
+            bytes[offset] = *tag;
+            bytes[offset + 1..offset + 4].copy_from_slice(payload);
+            bytes[offset + 4..offset + 8].copy_from_slice(&millis.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// A UF2 file of `bytes` at `addr`, 256 bytes per block like the bootloader.
+    fn uf2(bytes: &[u8], addr: u32) -> Vec<u8> {
+        let blocks = bytes.chunks(256).count() as u32;
+        let mut uf2 = Vec::new();
+        for (index, chunk) in bytes.chunks(256).enumerate() {
+            let mut block = vec![0; UF2_BLOCK_BYTES];
+            let words = [
+                UF2_MAGIC_START0,
+                UF2_MAGIC_START1,
+                0,
+                addr + index as u32 * 256,
+                chunk.len() as u32,
+                index as u32,
+                blocks,
+                0,
+            ];
+            for (offset, word) in words.iter().enumerate() {
+                block[offset * 4..offset * 4 + 4].copy_from_slice(&word.to_le_bytes());
+            }
+            block[32..32 + chunk.len()].copy_from_slice(chunk);
+            block[UF2_BLOCK_BYTES - 4..].copy_from_slice(&UF2_MAGIC_END.to_le_bytes());
+            uf2.extend(block);
+        }
+        uf2
+    }
+
+    /// The events and their records, as `src/flash_log.rs` encodes them.
+    fn events() -> Vec<(ControllerEvent, u8, [u8; 3])> {
+        vec![
+            (ControllerEvent::Battery(87), 1, [87, 0, 0]),
+            (ControllerEvent::ChargingState(true), 2, [1, 0, 0]),
+            (ControllerEvent::Layer(1), 3, [1, 0, 0]),
+            (ControllerEvent::ConnectionType(1), 4, [1, 0, 0]),
+            (ControllerEvent::SplitPeripheral(0, true), 5, [0, 1, 0]),
+            (ControllerEvent::SplitCentral(false), 6, [0, 0, 0]),
+            (ControllerEvent::Sleep(true), 7, [1, 0, 0]),
+            (
+                ControllerEvent::BleState(2, BleState::Connected),
+                8,
+                [2, 2, 0],
+            ),
+            (ControllerEvent::BleProfile(2), 9, [2, 0, 0]),
+            (ControllerEvent::ClearPeer, 10, [0, 0, 0]),
+        ]
+    }
+
+    /// The lines expected for the records of `events`, after a watchdog reset.
+    fn expected_lines() -> Vec<String> {
+        let mut lines = vec![
+            "--- boot 1 ---".to_string(),
+            "00:00:00.000  boot, reset watchdog".to_string(),
+        ];
+        for (index, (event, _, _)) in events().iter().enumerate() {
+            let millis = 1500 * (index as u32 + 1);
+            let text = format_event(event).unwrap();
+            lines.push(format!("{}  {text}", format_time(millis)));
+        }
+        lines
+    }
+
+    fn records() -> Vec<(u8, [u8; 3], u32)> {
+        let mut records = vec![(0, [2, 0, 0], 0)];
+        for (index, (_, tag, payload)) in events().into_iter().enumerate() {
+            records.push((tag, payload, 1500 * (index as u32 + 1)));
+        }
+        records
+    }
+
+    #[test]
+    fn raw_dump_reads_like_the_log_page() {
+        let flash = Flash::from_raw(&log_pages(&records()), BASE);
+        assert_eq!(log_lines(&flash, &layout()).unwrap(), expected_lines());
+    }
+
+    #[test]
+    fn uf2_dump_reads_like_the_log_page() {
+        // The UF2 file holds the whole flash, the log pages among the rest
+        let mut bytes = vec![0; 0x1000];
+        bytes.extend(log_pages(&records()));
+        let flash = Flash::from_uf2(&uf2(&bytes, BASE - 0x1000)).unwrap();
+        assert_eq!(log_lines(&flash, &layout()).unwrap(), expected_lines());
+    }
+
+    #[test]
+    fn pages_are_read_in_sequence_order() {
+        let mut bytes = log_pages(&[(0, [0, 0, 0], 0)]);
+        let page = PAGE_SIZE as usize;
+        bytes[page..page + 4].copy_from_slice(&FLASH_LOG_MAGIC.to_le_bytes());
+        bytes[page + 4..page + 8].copy_from_slice(&0u32.to_le_bytes());
+        bytes[page + 8..page + 16].copy_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        let flash = Flash::from_raw(&bytes, BASE);
+        assert_eq!(
+            log_lines(&flash, &layout()).unwrap(),
+            [
+                "--- boot 1 ---",
+                "00:00:00.000  boot, reset bouton",
+                "--- boot 2 ---",
+                "00:00:00.000  boot, reset allumage",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_uf2_block() {
+        let mut file = uf2(&[0; 256], BASE);
+        file[0] = 0;
+        assert_eq!(
+            Flash::from_uf2(&file).err().unwrap(),
+            "UF2 block 0 is invalid"
+        );
+    }
+
+    #[test]
+    fn dump_missing_the_log() {
+        let flash = Flash::from_raw(&[0xFF; 16], BASE);
+        assert!(log_lines(&flash, &layout())
+            .unwrap_err()
+            .starts_with("the dump does not cover the event log"));
+    }
+}
@@ -0,0 +1,525 @@
+//! Render the layers of `keyboard.toml` as a cheat sheet, in the terminal or as SVG files.
+//!
+//! ```sh
+//! keymap                  # ASCII, one block per layer
+//! keymap --svg keymap/    # one SVG file per layer, e.g. keymap/0-base.svg
+//! ```
+//!
+//! Each key shows its tap action, and under it its hold action for `MT`, `LT`, `TH` and the
+//! `TD(n)` entries of `behavior.morse.morses`. The keys are placed by their matrix position from
+//! `layout.matrix_map`, with a gap before the columns of each split peripheral.
+
+use std::{collections::BTreeSet, env, fmt::Write as _, fs, path::Path, process::ExitCode};
+
+/// Longest label of the ASCII mode, longer ones are cut.
+const ASCII_LABEL_MAX: usize = 9;
+/// Size of a key in the SVG, and the space between two keys.
+const SVG_KEY: u32 = 64;
+const SVG_KEY_GAP: u32 = 6;
+/// Extra space between the halves in the SVG.
+const SVG_HALF_GAP: u32 = 40;
+const SVG_MARGIN: u32 = 20;
+const SVG_TITLE: u32 = 36;
+
+/// What is printed on a key.
+struct Label {
+    tap: String,
+    hold: Option<String>,
+}
+
+struct Layer {
+    name: String,
+    /// Keys at their `(row, col)` position.
+    keys: Vec<((usize, usize), Label)>,
+}
+
+/// The parts of `keyboard.toml` the cheat sheet needs.
+struct Keymap {
+    rows: usize,
+    cols: usize,
+    /// First column of each split peripheral, where a gap is drawn.
+    half_starts: BTreeSet<usize>,
+    layers: Vec<Layer>,
+}
+
+/// Split `keys` on whitespace, keeping `MT(F5, LGui)` whole, like `build/validate.rs`.
+fn split_keys(keys: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    for (index, c) in keys.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(start) = start.take() {
+                    tokens.push(&keys[start..index]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(index);
+    }
+    if let Some(start) = start {
+        tokens.push(&keys[start..]);
+    }
+    tokens
+}
+
+/// Positions `(row, col)` of the `matrix_map`.
+fn matrix_map_positions(matrix_map: &str) -> Result<Vec<(usize, usize)>, String> {
+    matrix_map
+        .split('(')
+        .skip(1)
+        .map(|entry| {
+            let entry = entry.split(')').next().unwrap_or_default();
+            let mut fields = entry.split(',').map(|field| field.trim().parse().ok());
+            match (fields.next().flatten(), fields.next().flatten()) {
+                (Some(row), Some(col)) => Ok((row, col)),
+                _ => Err(format!("invalid matrix_map entry ({entry})")),
+            }
+        })
+        .collect()
+}
+
+/// Split `MT(A, LGui)` into `MT` and its arguments.
+fn call(action: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, args) = action.strip_suffix(')')?.split_once('(')?;
+    let mut split = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    for (index, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(args[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(args[start..].trim());
+    Some((name.trim(), split))
+}
+
+/// Short name of a keycode or modifier, like `;` for `Semicolon`.
+fn key_name(key: &str) -> String {
+    let short = match key {
+        "Semicolon" => ";",
+        "Quote" => "'",
+        "Grave" => "`",
+        "Backslash" => "\\",
+        "Minus" => "-",
+        "Equal" => "=",
+        "Comma" => ",",
+        "Dot" => ".",
+        "Slash" => "/",
+        "LeftBracket" => "[",
+        "RightBracket" => "]",
+        "Space" => "Space",
+        "Enter" => "Enter",
+        "Backspace" => "Bksp",
+        "Escape" => "Esc",
+        "Delete" => "Del",
+        "PageUp" => "PgUp",
+        "PageDown" => "PgDn",
+        "CapsLock" => "Caps",
+        "KbVolumeUp" => "Vol+",
+        "KbVolumeDown" => "Vol-",
+        "KbMute" => "Mute",
+        "MediaPlayPause" => "Play",
+        "MediaStop" => "Stop",
+        "MediaNextTrack" => "Next",
+        "MediaPrevTrack" => "Prev",
+        "MediaRewind" => "Rew",
+        "MediaFastForward" => "FFwd",
+        "MediaSelect" => "Select",
+        "BrightnessUp" => "Bri+",
+        "BrightnessDown" => "Bri-",
+        "No" => "",
+        "_" | "__" => "__",
+        _ => {
+            if let Some(digit) = key.strip_prefix("Kc").filter(|digit| digit.len() == 1) {
+                return digit.to_string();
+            }
+            if let Some(button) = key.strip_prefix("MouseBtn") {
+                return format!("Btn{button}");
+            }
+            if let Some(direction) = key.strip_prefix("Mouse") {
+                return format!("M{direction}");
+            }
+            key
+        }
+    };
+    short.to_string()
+}
+
+impl Keymap {
+    fn from_keyboard_toml(path: &str) -> Result<Self, String> {
+        let keyboard_toml: toml::Table = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {path}: {e}"))?
+            .parse()
+            .map_err(|e| format!("cannot parse {path}: {e}"))?;
+        let layout = keyboard_toml.get("layout").ok_or("[layout] is missing")?;
+        let size = |key: &str| {
+            layout
+                .get(key)
+                .and_then(toml::Value::as_integer)
+                .map(|size| size as usize)
+                .ok_or(format!("layout.{key} is not set"))
+        };
+        let (rows, cols) = (size("rows")?, size("cols")?);
+        let positions = matrix_map_positions(
+            layout
+                .get("matrix_map")
+                .and_then(toml::Value::as_str)
+                .ok_or("layout.matrix_map is not set")?,
+        )?;
+        if let Some((row, col)) = positions
+            .iter()
+            .find(|(row, col)| *row >= rows || *col >= cols)
+        {
+            return Err(format!(
+                "matrix_map position ({row},{col}) is outside of the layout"
+            ));
+        }
+
+        let half_starts = keyboard_toml
+            .get("split")
+            .and_then(|split| split.get("peripheral"))
+            .and_then(toml::Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|peripheral| peripheral.get("col_offset")?.as_integer())
+            .filter(|&col_offset| col_offset > 0)
+            .map(|col_offset| col_offset as usize)
+            .collect();
+
+        let layer_tables = keyboard_toml
+            .get("layer")
+            .and_then(toml::Value::as_array)
+            .ok_or("no [[layer]] in keyboard.toml")?;
+        let layer_names: Vec<String> = layer_tables
+            .iter()
+            .enumerate()
+            .map(|(index, layer)| {
+                layer
+                    .get("name")
+                    .and_then(toml::Value::as_str)
+                    .map_or_else(|| index.to_string(), str::to_string)
+            })
+            .collect();
+        let morses = keyboard_toml
+            .get("behavior")
+            .and_then(|behavior| behavior.get("morse"))
+            .and_then(|morse| morse.get("morses"))
+            .and_then(toml::Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let labels = Labels {
+            layer_names: &layer_names,
+            morses,
+        };
+
+        let mut layers = Vec::new();
+        for (layer_table, name) in layer_tables.iter().zip(&layer_names) {
+            let keys = split_keys(
+                layer_table
+                    .get("keys")
+                    .and_then(toml::Value::as_str)
+                    .ok_or(format!("layer {name} has no keys"))?,
+            );
+            if keys.len() != positions.len() {
+                return Err(format!(
+                    "layer {name} has {} keys for {} matrix_map positions",
+                    keys.len(),
+                    positions.len()
+                ));
+            }
+            layers.push(Layer {
+                name: name.clone(),
+                keys: positions
+                    .iter()
+                    .zip(keys)
+                    .map(|(&position, key)| (position, labels.label(key)))
+                    .collect(),
+            });
+        }
+        Ok(Self {
+            rows,
+            cols,
+            half_starts,
+            layers,
+        })
+    }
+}
+
+/// What the labels of the keys refer to.
+struct Labels<'a> {
+    layer_names: &'a [String],
+    morses: &'a [toml::Value],
+}
+
+impl Labels<'_> {
+    fn layer(&self, index: &str) -> String {
+        index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| self.layer_names.get(index))
+            .cloned()
+            .unwrap_or_else(|| index.to_string())
+    }
+
+    /// Label of a tap or a hold action, without the tap-hold ones.
+    fn action(&self, action: &str) -> String {
+        let Some((name, args)) = call(action) else {
+            return key_name(action);
+        };
+        match (name, args.as_slice()) {
+            ("WM", [key, modifiers]) => format!("{modifiers}+{}", key_name(key)),
+            ("MO" | "TO" | "TG" | "DF" | "OSL" | "TT", [layer]) => {
+                format!("{name} {}", self.layer(layer))
+            }
+            ("LM", [layer, modifiers]) => format!("{modifiers}+{}", self.layer(layer)),
+            ("OSM", [modifiers]) => format!("OS {modifiers}"),
+            _ => action.to_string(),
+        }
+    }
+
+    fn label(&self, action: &str) -> Label {
+        let tap_hold = |tap: String, hold: String| Label {
+            tap,
+            hold: Some(hold),
+        };
+        let call = call(action);
+        match call.as_ref().map(|(name, args)| (*name, args.as_slice())) {
+            Some(("MT", [key, modifiers, ..])) => tap_hold(key_name(key), modifiers.to_string()),
+            Some(("LT", [layer, key, ..])) => tap_hold(key_name(key), self.layer(layer)),
+            Some(("TH", [tap, hold, ..])) => tap_hold(self.action(tap), self.action(hold)),
+            Some(("TD", [index])) => {
+                let morse = index
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| self.morses.get(index));
+                let Some(morse) = morse else {
+                    return Label {
+                        tap: action.to_string(),
+                        hold: None,
+                    };
+                };
+                // A morse has `tap`/`hold`, or the actions of each tap count in `tap_actions`/`hold_actions`
+                let first = |single: &str, list: &str| {
+                    morse
+                        .get(single)
+                        .or_else(|| morse.get(list)?.as_array()?.first())
+                        .and_then(toml::Value::as_str)
+                        .map(|action| self.action(action))
+                };
+                Label {
+                    tap: first("tap", "tap_actions").unwrap_or_default(),
+                    hold: first("hold", "hold_actions"),
+                }
+            }
+            _ => Label {
+                tap: self.action(action),
+                hold: None,
+            },
+        }
+    }
+}
+
+/// Cut `text` to `max` characters, marking the cut with `~`.
+fn cut(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(max - 1).collect();
+    cut.push('~');
+    cut
+}
+
+fn ascii_layer(keymap: &Keymap, index: usize, layer: &Layer) -> String {
+    let width = layer
+        .keys
+        .iter()
+        .flat_map(|(_, label)| {
+            [
+                label.tap.chars().count(),
+                label.hold.as_ref().map_or(0, |hold| hold.chars().count()),
+            ]
+        })
+        .max()
+        .unwrap_or(0)
+        .clamp(3, ASCII_LABEL_MAX);
+    let mut text = format!("Layer {index}: {}\n", layer.name);
+    for row in 0..keymap.rows {
+        let mut lines = [String::new(), String::new(), String::new(), String::new()];
+        for col in 0..keymap.cols {
+            let gap = if keymap.half_starts.contains(&col) {
+                "    "
+            } else if col > 0 {
+                " "
+            } else {
+                ""
+            };
+            lines.iter_mut().for_each(|line| line.push_str(gap));
+            let key = layer
+                .keys
+                .iter()
+                .find(|(position, _)| *position == (row, col));
+            let cells = match key {
+                Some((_, label)) => {
+                    let border = format!("+{}+", "-".repeat(width + 2));
+                    let hold = label.hold.as_deref().unwrap_or_default();
+                    [
+                        border.clone(),
+                        format!("| {:^width$} |", cut(&label.tap, width)),
+                        format!("| {:^width$} |", cut(hold, width)),
+                        border,
+                    ]
+                }
+                None => [(); 4].map(|_| " ".repeat(width + 4)),
+            };
+            for (line, cell) in lines.iter_mut().zip(cells) {
+                line.push_str(&cell);
+            }
+        }
+        for line in lines {
+            let line = line.trim_end();
+            if !line.is_empty() {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+    text
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Font size fitting `text` in a key, at most `max`.
+fn font_size(text: &str, max: u32) -> u32 {
+    let chars = text.chars().count().max(1) as u32;
+    max.min((SVG_KEY - 8) * 10 / (chars * 6)).max(6)
+}
+
+fn svg_layer(keymap: &Keymap, index: usize, layer: &Layer) -> String {
+    let x = |col: usize| {
+        let gaps = keymap.half_starts.range(..=col).count() as u32;
+        SVG_MARGIN + col as u32 * (SVG_KEY + SVG_KEY_GAP) + gaps * SVG_HALF_GAP
+    };
+    let y = |row: usize| SVG_MARGIN + SVG_TITLE + row as u32 * (SVG_KEY + SVG_KEY_GAP);
+    let width = x(keymap.cols) - SVG_KEY_GAP + SVG_MARGIN;
+    let height = y(keymap.rows) - SVG_KEY_GAP + SVG_MARGIN;
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif" text-anchor="middle">"#
+    )
+    .unwrap();
+    writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+    writeln!(
+        svg,
+        r#"<text x="{SVG_MARGIN}" y="{}" font-size="20" font-weight="bold" text-anchor="start">Layer {index}: {}</text>"#,
+        SVG_MARGIN + 20,
+        xml_escape(&layer.name)
+    )
+    .unwrap();
+    for ((row, col), label) in &layer.keys {
+        let (x, y) = (x(*col), y(*row));
+        let center = x + SVG_KEY / 2;
+        writeln!(
+            svg,
+            r##"<rect x="{x}" y="{y}" width="{SVG_KEY}" height="{SVG_KEY}" rx="6" fill="#f4f4f4" stroke="#333"/>"##
+        )
+        .unwrap();
+        let tap_y = if label.hold.is_some() { y + 28 } else { y + 37 };
+        writeln!(
+            svg,
+            r#"<text x="{center}" y="{tap_y}" font-size="{}">{}</text>"#,
+            font_size(&label.tap, 16),
+            xml_escape(&label.tap)
+        )
+        .unwrap();
+        if let Some(hold) = &label.hold {
+            writeln!(
+                svg,
+                r##"<text x="{center}" y="{}" font-size="{}" fill="#b04000">{}</text>"##,
+                y + 52,
+                font_size(hold, 11),
+                xml_escape(hold)
+            )
+            .unwrap();
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// File name of a layer's SVG, `0-base.svg`.
+fn svg_file_name(index: usize, layer: &Layer) -> String {
+    let name: String = layer
+        .name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("{index}-{name}.svg")
+}
+
+fn run() -> Result<(), String> {
+    let mut keyboard_toml = "keyboard.toml".to_string();
+    let mut svg_dir = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keyboard-toml" => keyboard_toml = args.next().ok_or("missing keyboard.toml path")?,
+            "--svg" => svg_dir = Some(args.next().ok_or("missing SVG directory")?),
+            _ => {
+                return Err(format!(
+                    "unexpected argument {arg}\nusage: keymap [--keyboard-toml PATH] [--svg DIR]"
+                ))
+            }
+        }
+    }
+
+    let keymap = Keymap::from_keyboard_toml(&keyboard_toml)?;
+    match svg_dir {
+        Some(dir) => {
+            fs::create_dir_all(&dir).map_err(|e| format!("cannot create {dir}: {e}"))?;
+            for (index, layer) in keymap.layers.iter().enumerate() {
+                let path = Path::new(&dir).join(svg_file_name(index, layer));
+                fs::write(&path, svg_layer(&keymap, index, layer))
+                    .map_err(|e| format!("cannot write {}: {e}", path.display()))?;
+                println!("{}", path.display());
+            }
+        }
+        None => {
+            let layers: Vec<String> = keymap
+                .layers
+                .iter()
+                .enumerate()
+                .map(|(index, layer)| ascii_layer(&keymap, index, layer))
+                .collect();
+            print!("{}", layers.join("\n"));
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("keymap: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! Host tests of the modules of the build script: `cargo make test-tools`.
+
+#[allow(dead_code)]
+#[path = "../../build/board.rs"]
+mod board;
+#[allow(dead_code)]
+#[path = "../../build/validate.rs"]
+mod validate;
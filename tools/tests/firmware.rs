@@ -0,0 +1,6 @@
+//! Host tests of the firmware modules which do not depend on the nRF: `cargo make test-tools`.
+
+#[path = "../../src/battery_curve.rs"]
+mod battery_curve;
+#[path = "../../src/settings_store.rs"]
+mod settings_store;